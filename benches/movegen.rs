@@ -0,0 +1,95 @@
+/*
+    Benchmark of the bitboard-based move generation (`moves::piece_moves`) against the generic one (`moves::piece_moves_generic`).
+
+    Run it with `cargo bench --bench movegen`. The boards of the starting positions of every variant, and of the positions
+    following their first movesets, are used; both move generators are checked to yield the same moves on each of them.
+*/
+
+use chess5dlib::{game::*, moves::*, notation, variants::*};
+use std::time::{Duration, Instant};
+
+/// Number of first movesets kept per variant
+const MAX_MOVESETS: usize = 64;
+/// Number of times that the move generators go through every board
+const ROUNDS: usize = 200;
+
+fn main() {
+    let mut total_generic = Duration::default();
+    let mut total_bitboards = Duration::default();
+
+    for variant in VARIANTS.iter() {
+        let game = variant.game();
+        let empty: Vec<&Board> = Vec::new();
+        let mut positions: Vec<Vec<Board>> = vec![Vec::new()];
+        positions.extend(
+            legal_movesets(&game, &game.info, &empty, 0, 0)
+                .take(MAX_MOVESETS)
+                .map(|(_moves, boards, _info, _score)| boards),
+        );
+
+        let mut n_boards = 0;
+        let mut n_moves = 0;
+        let mut generic = Duration::default();
+        let mut bitboards = Duration::default();
+        for boards in &positions {
+            let virtual_boards: Vec<&Board> = boards.iter().collect();
+            let all_boards = game
+                .timelines
+                .values()
+                .flat_map(|tl| tl.states.iter())
+                .chain(boards.iter())
+                .collect::<Vec<_>>();
+
+            for board in &all_boards {
+                let mut expected = piece_moves_generic(&game, board, &virtual_boards);
+                let mut actual = piece_moves(&game, board, &virtual_boards);
+                expected.sort_by_key(|mv| format!("{:?}", mv));
+                actual.sort_by_key(|mv| format!("{:?}", mv));
+                assert_eq!(
+                    format!("{:?}", expected),
+                    format!("{:?}", actual),
+                    "The move generators disagree on {} in {}",
+                    notation::write_board(board, game.info.even_initial_timelines),
+                    variant.name()
+                );
+                n_moves += actual.len();
+            }
+            n_boards += all_boards.len();
+
+            let begin = Instant::now();
+            for _ in 0..ROUNDS {
+                for board in &all_boards {
+                    std::hint::black_box(piece_moves_generic(&game, board, &virtual_boards));
+                }
+            }
+            generic += begin.elapsed();
+
+            let begin = Instant::now();
+            for _ in 0..ROUNDS {
+                for board in &all_boards {
+                    std::hint::black_box(piece_moves(&game, board, &virtual_boards));
+                }
+            }
+            bitboards += begin.elapsed();
+        }
+
+        println!(
+            "{:<16} {:>5} boards, {:>6} moves: generic {:>8.2} ms, bitboards {:>8.2} ms, x{:.2}",
+            variant.name(),
+            n_boards,
+            n_moves,
+            generic.as_secs_f64() * 1000.0,
+            bitboards.as_secs_f64() * 1000.0,
+            generic.as_secs_f64() / bitboards.as_secs_f64()
+        );
+        total_generic += generic;
+        total_bitboards += bitboards;
+    }
+
+    println!(
+        "Total: generic {:.2} ms, bitboards {:.2} ms, x{:.2}",
+        total_generic.as_secs_f64() * 1000.0,
+        total_bitboards.as_secs_f64() * 1000.0,
+        total_generic.as_secs_f64() / total_bitboards.as_secs_f64()
+    );
+}
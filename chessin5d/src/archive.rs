@@ -0,0 +1,181 @@
+/*
+    Archive of the bot's games: once a session ends, its game is written to the archive directory (see `Config::archive`)
+    as `<id>.5dpgn`, along with the server's last JSON representation of the session, as `<id>.json`.
+
+    The server only gives out positions, so the movesets are rebuilt by `History` while the game is being played:
+    every time the bot sees a new position, it looks for the moves that lead to it from the last position it knows of.
+*/
+
+use super::Session;
+use chess5dlib::{game::*, moves::*, notation::{self, GameRecord}, replay, variants::Variant};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// The movesets of a session, rebuilt out of the positions that the bot saw
+pub struct History {
+    /// The game played so far; `None` once the movesets leading to a position couldn't be found
+    pub record: Option<GameRecord>,
+}
+
+impl History {
+    /// Starts from the initial position of the session's variant, and catches up with the session's current position
+    pub fn new(session: &Session) -> Self {
+        let mut history = History {
+            record: Variant::from_name(&session.variant)
+                .and_then(|variant| notation::parse(&format!("[Board \"{}\"]\n", variant.name())).ok()),
+        };
+        history.update(&session.game);
+        history
+    }
+
+    /// Finds the movesets played between the last known position and `game`, and plays them on the record
+    pub fn update(&mut self, game: &Game) {
+        while let Some(record) = &mut self.record {
+            if count_boards(&record.game) >= count_boards(game) {
+                break;
+            }
+            match find_moveset(&record.game, game) {
+                Some(moveset) => {
+                    // `find_moveset` only returns legal movesets
+                    replay::play_moveset(&mut record.game, &moveset).unwrap();
+                    record.turns.push(moveset);
+                }
+                None => self.record = None,
+            }
+        }
+    }
+}
+
+/**
+    Writes the game of the ended `session` to `directory`, as `<id>.5dpgn` and `<id>.json` (`raw` being the server's JSON of the session).
+    The headers of the 5dpgn file hold the players, variant, time control, ranking and dates of the session.
+    If its movesets couldn't all be found, the game is written as a custom position: the last one that the bot saw.
+**/
+pub fn save(directory: &str, session: &Session, history: &History, raw: Option<&str>) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+
+    // `notation::write` replays the turns from the initial boards, so the records are made without their game
+    let mut record = match &history.record {
+        Some(record) => GameRecord {
+            headers: record.headers.clone(),
+            initial_boards: record.initial_boards.clone(),
+            width: record.width,
+            height: record.height,
+            even_initial_timelines: record.even_initial_timelines,
+            turns: record.turns.clone(),
+            game: Game::new(0, 0),
+        },
+        None => {
+            let mut timelines = session.game.timelines.values().collect::<Vec<_>>();
+            timelines.sort_by_key(|tl| tl.index);
+            GameRecord {
+                headers: vec![(String::from("Board"), String::from("custom"))],
+                initial_boards: timelines.into_iter().flat_map(|tl| tl.states.iter().cloned()).collect(),
+                width: session.game.width,
+                height: session.game.height,
+                even_initial_timelines: session.game.info.even_initial_timelines,
+                turns: vec![],
+                game: Game::new(0, 0),
+            }
+        }
+    };
+    record.headers.extend(headers(session));
+
+    let written = notation::write(&record).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))?;
+    let path = Path::new(directory).join(format!("{}.5dpgn", session.id));
+    std::fs::write(&path, written)?;
+    if let Some(raw) = raw {
+        std::fs::write(Path::new(directory).join(format!("{}.json", session.id)), raw)?;
+    }
+
+    Ok(path)
+}
+
+/// The metadata of `session`, as 5dpgn headers
+fn headers(session: &Session) -> Vec<(String, String)> {
+    let mut res = vec![
+        (String::from("Session"), session.id.clone()),
+        (String::from("Variant"), session.variant.clone()),
+        (String::from("White"), session.white.clone().unwrap_or_default()),
+        (String::from("Black"), session.black.clone().unwrap_or_default()),
+        (String::from("Format"), session.format.clone()),
+        (String::from("Ranked"), session.ranked.to_string()),
+        (String::from("Date"), write_date(session.start_date)),
+        (String::from("StartDate"), session.start_date.to_string()),
+        (String::from("EndDate"), session.end_date.to_string()),
+    ];
+    let result = match &session.winner {
+        Some(winner) if Some(winner) == session.white.as_ref() => "1-0",
+        Some(_) => "0-1",
+        None => "1/2-1/2",
+    };
+    res.push((String::from("Result"), String::from(result)));
+    if let Some(cause) = &session.win_cause {
+        res.push((String::from("Termination"), cause.clone()));
+    }
+    res
+}
+
+/**
+    Looks for the moveset of the active player of `before` that leads to (or towards, if several movesets were played since) `after`.
+    The moves are picked one at a time, among the ones whose boards are all found in `after`;
+    the order in which they are tried matters for the moves creating timelines, which is why every move is tried again after each pick.
+**/
+fn find_moveset(before: &Game, after: &Game) -> Option<Vec<Move>> {
+    let mut info = before.info;
+    let mut boards: Vec<Board> = Vec::new();
+    let mut played: HashSet<(i32, isize)> = HashSet::new();
+    let mut moveset: Vec<Move> = Vec::new();
+
+    let candidates = get_own_boards(before, &vec![], &before.info)
+        .into_iter()
+        .flat_map(|board| probable_moves(before, board, &vec![]))
+        .flat_map(|mv| -> Vec<Move> {
+            match mv.promotion {
+                Some(_) => promotion_pieces(before, before.info.active_player)
+                    .into_iter()
+                    .map(|piece| Move { promotion: Some(piece), ..mv })
+                    .collect(),
+                None => vec![mv],
+            }
+        })
+        .collect::<Vec<_>>();
+
+    'picking: loop {
+        for mv in candidates.iter().filter(|mv| !played.contains(&(mv.src.0, mv.src.1))) {
+            if let Some((new_info, new_boards)) = mv.generate_vboards(before, &info, &vec![], &boards) {
+                let matches = new_boards.iter().all(|b| after.get_board(b.l, b.t).map(|a| a.squares == b.squares).unwrap_or(false));
+                if matches {
+                    played.insert((mv.src.0, mv.src.1));
+                    boards.extend(new_boards);
+                    info = new_info;
+                    moveset.push(*mv);
+                    continue 'picking;
+                }
+            }
+        }
+        break;
+    }
+
+    replay::validate_moveset(before, &moveset).ok()?;
+    Some(moveset)
+}
+
+fn count_boards(game: &Game) -> usize {
+    game.timelines.values().map(|tl| tl.states.len()).sum()
+}
+
+/// Writes the date of a timestamp (in milliseconds since the Unix epoch) as `YYYY.MM.DD`
+fn write_date(ms: u128) -> String {
+    // Days to civil date conversion, from http://howardhinnant.github.io/date_algorithms.html
+    let days = (ms / 86_400_000) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
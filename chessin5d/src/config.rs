@@ -0,0 +1,276 @@
+/*
+    Configuration of the bot, read from `./config.ron` on startup (see `config.ron.sample`).
+
+    Besides the account's credentials, it tells how many games the bot plays at once, how often it polls the server,
+    which sessions it joins (variants, time controls and colour) and how it searches, possibly with settings specific to a variant.
+    Every field but the credentials has a default; `Config::load` checks the values and reports the first invalid one.
+*/
+
+use super::{Color, Session};
+use chess5dlib::variants::Variant;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    pub username: String,
+    pub password: String,
+    pub bio: String,
+    pub fullname: String,
+    pub hostname: String,
+    /// The bot resigns once its position is scored below this
+    #[serde(default = "default_resign_threshold")]
+    pub resign_threshold: f32,
+    /// The bot accepts the draw offers made while its position is scored below this, and declines the others
+    #[serde(default)]
+    pub draw_threshold: f32,
+    /// File to which the results of the games are appended
+    #[serde(default = "default_results")]
+    pub results: String,
+    /// Directory in which the games are saved once they have ended
+    #[serde(default = "default_archive")]
+    pub archive: String,
+    /// Maximum number of sessions that the bot takes part in at once
+    #[serde(default = "default_max_games")]
+    pub max_games: usize,
+    /// Time (in seconds) after which the bot removes the sessions that it hosts and that nobody joined
+    #[serde(default = "default_new_game_timeout")]
+    pub new_game_timeout: f64,
+    /// Time (in seconds) between two polls of the sessions list, and of each session being played
+    #[serde(default = "default_ping_interval")]
+    pub ping_interval: f64,
+    /// Colour played by the bot: it only joins the sessions in which that colour is free, and hosts its sessions (`--new-session`) with it
+    #[serde(default = "default_color")]
+    pub color: Color,
+    /// Variants of the sessions that the bot joins, as named by the server or by `Variant::from_name`; any variant if empty.
+    /// The names unknown to `Variant::from_name` (eg. the variants that the bot can't play yet) are compared as they are written
+    #[serde(default)]
+    pub variants: Vec<String>,
+    /// Time controls of the sessions that the bot joins, as given by the server (eg. `"10+5"`); any time control if empty
+    #[serde(default)]
+    pub formats: Vec<String>,
+    /// Search settings used in the variants that aren't in `variant_search`
+    #[serde(default)]
+    pub search: SearchConfig,
+    /// Search settings of specific variants, by variant name; the fields left out take their default value (not the one of `search`)
+    #[serde(default)]
+    pub variant_search: HashMap<String, SearchConfig>,
+}
+
+/// Parameters of `iddfs::iddfs_bfs`, and of the time given to it on each turn
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// Maximum number of movesets to consider until a position is deemed to be draw or checkmate
+    pub max_ms: usize,
+    pub bucket_size: usize,
+    /// Desired number of IDDFS tasks
+    pub pool_size: usize,
+    pub threads: u32,
+    /// Number of entries of the transposition table
+    pub tt_size: usize,
+    /// The time of a turn is the increment plus an even share of the initial time over `expected_turns` turns
+    pub expected_turns: f64,
+    /// Share of the turn's time given to the search; the rest is left to submit the moves
+    pub time_margin: f64,
+    /// Minimum time (in seconds) given to the search
+    pub min_move_time: f64,
+    /// Time (in seconds) given to the search in untimed games, or if the time control couldn't be read
+    pub default_move_time: f64,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            max_ms: 10000,
+            bucket_size: 64,
+            pool_size: 1024,
+            threads: 4,
+            tt_size: 1 << 18,
+            expected_turns: 40.0,
+            time_margin: 0.75,
+            min_move_time: 1.0,
+            default_move_time: 10.0,
+        }
+    }
+}
+
+fn default_resign_threshold() -> f32 {
+    -50.0
+}
+
+fn default_results() -> String {
+    String::from("./results.jsonl")
+}
+
+fn default_archive() -> String {
+    String::from("./archive")
+}
+
+fn default_max_games() -> usize {
+    2
+}
+
+fn default_new_game_timeout() -> f64 {
+    60.0 * 5.0
+}
+
+fn default_ping_interval() -> f64 {
+    5.0
+}
+
+fn default_color() -> Color {
+    Color::Random
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// The configuration file couldn't be read
+    Io(String, String),
+    /// The configuration file isn't valid RON, or doesn't match `Config`
+    Parse(String),
+    /// A field holds an invalid value; holds the field's name and what is wrong with it
+    Invalid(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "couldn't read {}: {}", path, err),
+            ConfigError::Parse(err) => write!(f, "couldn't parse the configuration: {}", err),
+            ConfigError::Invalid(field, reason) => write!(f, "invalid `{}`: {}", field, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Reads and validates the configuration at `path`
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let raw = std::fs::read_to_string(path).map_err(|err| ConfigError::Io(String::from(path), err.to_string()))?;
+        let config = ron::from_str::<Config>(&raw).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that the values of the configuration can be used by the bot
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.username.is_empty() || !self.username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return invalid("username", "it may only contain letters, digits, `_` and `-`");
+        }
+        if !self.hostname.starts_with("http://") && !self.hostname.starts_with("https://") {
+            return invalid("hostname", "it should start with `http://` or `https://`");
+        }
+        if self.resign_threshold.is_nan() {
+            return invalid("resign_threshold", "it isn't a number");
+        }
+        if self.draw_threshold.is_nan() {
+            return invalid("draw_threshold", "it isn't a number");
+        }
+        if self.results.is_empty() {
+            return invalid("results", "it should be a path");
+        }
+        if self.archive.is_empty() {
+            return invalid("archive", "it should be a path");
+        }
+        if self.max_games == 0 {
+            return invalid("max_games", "the bot should be able to play at least one game");
+        }
+        if !self.new_game_timeout.is_finite() || self.new_game_timeout < 0.0 {
+            return invalid("new_game_timeout", "it should be a positive number of seconds");
+        }
+        if !self.ping_interval.is_finite() || self.ping_interval <= 0.0 {
+            return invalid("ping_interval", "it should be a positive number of seconds");
+        }
+        if self.variants.iter().any(|variant| variant.trim().is_empty()) {
+            return invalid("variants", "a variant is empty");
+        }
+        if self.formats.iter().any(|format| format.trim().is_empty()) {
+            return invalid("formats", "a time control is empty");
+        }
+        self.search.validate("search")?;
+        for (variant, search) in &self.variant_search {
+            if variant.trim().is_empty() {
+                return invalid("variant_search", "a variant is empty");
+            }
+            search.validate(&format!("variant_search[\"{}\"]", variant))?;
+        }
+        Ok(())
+    }
+
+    pub fn ping_interval(&self) -> Duration {
+        Duration::from_secs_f64(self.ping_interval)
+    }
+
+    /// `new_game_timeout`, in milliseconds
+    pub fn new_game_timeout_ms(&self) -> u128 {
+        (self.new_game_timeout * 1000.0) as u128
+    }
+
+    /// Returns the search settings of `variant`
+    pub fn search_for(&self, variant: &str) -> SearchConfig {
+        self.variant_search
+            .iter()
+            .find(|(name, _)| same_variant(name, variant))
+            .map(|(_, search)| *search)
+            .unwrap_or(self.search)
+    }
+
+    /// Returns whether or not the bot should join `session`, given its variant, its time control and the colour left to the bot
+    pub fn accepts(&self, session: &Session) -> bool {
+        let variant = self.variants.is_empty() || self.variants.iter().any(|name| same_variant(name, &session.variant));
+        let format = self.formats.is_empty() || self.formats.iter().any(|format| format.trim() == session.format.trim());
+        let color = match self.color {
+            Color::White => session.white.is_none() || session.white.as_deref() == Some(&self.username),
+            Color::Black => session.black.is_none() || session.black.as_deref() == Some(&self.username),
+            Color::Random => true,
+        };
+        variant && format && color
+    }
+}
+
+impl SearchConfig {
+    /// Checks the settings, which are found at `field` in the configuration
+    fn validate(&self, field: &str) -> Result<(), ConfigError> {
+        let positive = [
+            ("max_ms", self.max_ms),
+            ("bucket_size", self.bucket_size),
+            ("pool_size", self.pool_size),
+            ("threads", self.threads as usize),
+            ("tt_size", self.tt_size),
+        ];
+        for (name, value) in positive.iter() {
+            if *value == 0 {
+                return invalid(&format!("{}.{}", field, name), "it should be at least 1");
+            }
+        }
+        if !self.expected_turns.is_finite() || self.expected_turns <= 0.0 {
+            return invalid(&format!("{}.expected_turns", field), "it should be a positive number");
+        }
+        if !(self.time_margin > 0.0 && self.time_margin <= 1.0) {
+            return invalid(&format!("{}.time_margin", field), "it should lie within ]0, 1]");
+        }
+        if !self.min_move_time.is_finite() || self.min_move_time < 0.0 {
+            return invalid(&format!("{}.min_move_time", field), "it should be a positive number of seconds");
+        }
+        if !self.default_move_time.is_finite() || self.default_move_time <= 0.0 {
+            return invalid(&format!("{}.default_move_time", field), "it should be a positive number of seconds");
+        }
+        Ok(())
+    }
+}
+
+fn invalid(field: &str, reason: &str) -> Result<(), ConfigError> {
+    Err(ConfigError::Invalid(String::from(field), String::from(reason)))
+}
+
+/// Compares two variant names, which may be written in any of the forms accepted by `Variant::from_name`
+fn same_variant(a: &str, b: &str) -> bool {
+    match (Variant::from_name(a), Variant::from_name(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.trim().eq_ignore_ascii_case(b.trim()),
+    }
+}
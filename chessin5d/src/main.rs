@@ -5,29 +5,26 @@ extern crate roy;
 extern crate lazy_static;
 extern crate tokio;
 
-use chess5dlib::{game::*, moves::*, moveset::*, resolve::*, tree::*};
+use chess5dlib::{game::*, moves::*, moveset::*, resolve::*, tree::*, transposition::TranspositionTable};
 use serde::{Deserialize};
-use std::fs::File;
-use std::io::prelude::*;
 use roy::Client;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::collections::HashMap;
-use tokio::{time, runtime, task::JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet};
+use tokio::{time, runtime, task::{self, JoinHandle}};
 use tokio::join;
+use config::{Config, SearchConfig};
 
+pub mod archive;
+pub mod config;
 pub mod request;
+pub mod results;
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
 
-#[derive(Deserialize)]
-pub struct Config {
-    pub username: String,
-    pub password: String,
-    pub bio: String,
-    pub fullname: String,
-    pub hostname: String,
-}
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum Color {
     White,
     Black,
@@ -63,18 +60,17 @@ pub enum Message {
     Log(String),
 }
 
-const MAX_GAMES: usize = 2;
-const NEW_GAME_TIMEOUT: u128 = 60 * 5 * 1000;
-const PING_INTERVAL: u64 = 5;
-
 type SessionReturnType = ();
 
 #[tokio::main]
 async fn main() {
-    let mut config_file = File::open("./config.ron").expect("Coudln't open file ./config.ron!");
-    let mut config_raw = String::new();
-    config_file.read_to_string(&mut config_raw).expect("Couldn't read config!");
-    let config = ron::from_str::<Config>(&config_raw).expect("Couldn't parse config!");
+    let config = match Config::load("./config.ron") {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Error in ./config.ron: {}; exiting!", err);
+            return;
+        }
+    };
 
     let client = Client::new(config.hostname.clone());
 
@@ -95,57 +91,23 @@ async fn main() {
 
     let rt = runtime::Runtime::new().unwrap();
 
+    let config = Arc::new(config);
     let ping_handle = {
-        let username = config.username.clone();
+        let config = Arc::clone(&config);
         let client = Arc::clone(&client);
         rt.spawn(async move {
-            let mut interval = time::interval(time::Duration::from_secs(PING_INTERVAL));
+            let mut interval = time::interval(config.ping_interval());
             let mut started_sessions: HashMap<String, JoinHandle<SessionReturnType>> = HashMap::new();
             let mut ready_sessions: HashMap<String, Session> = HashMap::new();
             loop {
                 interval.tick().await;
-                let new_sessions = handle_sessions(client.clone()).await;
-                for sess in new_sessions.into_iter() {
-                    if !sess.started && now() - sess.start_date >= NEW_GAME_TIMEOUT {
-                        if &sess.host == &username {
-                            println!("[Removing session {}]", sess.id);
-                            if !request::remove_session(&client, sess.id.clone()).await {
-                                println!("Coudln't remove session {}!", sess.id);
-                            }
-                        }
-                        continue;
-                    } else if started_sessions.get(&sess.id).is_some() {
-                        continue;
-                    } else if ready_sessions.get(&sess.id).is_some() {
-                        if sess.started {
-                            println!("[Starting session {}]", sess.id);
-                            ready_sessions.remove(&sess.id);
-                            let client = Arc::clone(&client);
-                            let white = sess.white == Some(username.clone());
-                            started_sessions.insert(sess.id.clone(), tokio::spawn(async move {
-                                handle_session(client, white, sess).await
-                            }));
-                        }
-                    } else {
-                        if !sess.ready && !sess.started {
-                            println!("[Getting ready for session {}]", sess.id);
-                            if request::session_ready(&client, sess.id.clone()).await {
-                                ready_sessions.insert(sess.id.clone(), sess);
-                            } else {
-                                println!("Couldn't flag ourselves as ready!");
-                            }
-                        } else {
-                            println!("[Catch up ready session: {}]", sess.id);
-                            ready_sessions.insert(sess.id.clone(), sess);
-                        }
-                    }
-                }
+                poll_sessions(&client, &config, &mut ready_sessions, &mut started_sessions).await;
             }
         })
     };
 
     if std::env::args().find(|x| x == "--new-session").is_some() {
-        let session = request::new_session(&client, Color::White).await;
+        let session = request::new_session(&client, config.color).await;
         match session {
             Some(info) => {
                 println!("Session created!");
@@ -177,7 +139,66 @@ async fn main() {
     ping_handle.await.unwrap();
 }
 
-async fn handle_sessions(client: Arc<Client>) -> Vec<Session> {
+/**
+    Looks for new sessions once: flags the bot as ready in the sessions that it can join (see `Config::accepts`), starts playing the ready sessions
+    once they have started and removes the bot's sessions that nobody joined within `config.new_game_timeout`.
+    The games are played by `handle_session`; they are forgotten once they have ended.
+**/
+async fn poll_sessions(
+    client: &Arc<Client>,
+    config: &Arc<Config>,
+    ready_sessions: &mut HashMap<String, Session>,
+    started_sessions: &mut HashMap<String, JoinHandle<SessionReturnType>>,
+) {
+    let username = config.username.as_str();
+    let new_sessions = handle_sessions(client.clone(), config.max_games).await;
+    // Ended sessions aren't listed anymore
+    started_sessions.retain(|id, _| new_sessions.iter().any(|sess| sess.id == *id));
+    ready_sessions.retain(|id, _| new_sessions.iter().any(|sess| sess.id == *id));
+
+    for sess in new_sessions.into_iter() {
+        if !sess.started && now() - sess.start_date >= config.new_game_timeout_ms() {
+            if sess.host == username {
+                println!("[Removing session {}]", sess.id);
+                if !request::remove_session(client, sess.id.clone()).await {
+                    println!("Coudln't remove session {}!", sess.id);
+                }
+            }
+            continue;
+        } else if started_sessions.contains_key(&sess.id) {
+            continue;
+        } else if ready_sessions.contains_key(&sess.id) {
+            if sess.started {
+                println!("[Starting session {}]", sess.id);
+                ready_sessions.remove(&sess.id);
+                let client = Arc::clone(client);
+                let config = Arc::clone(config);
+                let white = sess.white.as_deref() == Some(username);
+                started_sessions.insert(sess.id.clone(), tokio::spawn(async move {
+                    handle_session(client, config, white, sess).await
+                }));
+            }
+        } else {
+            // The sessions that the bot doesn't want to play are skipped silently, as they are listed again on every poll
+            if sess.host != username && !config.accepts(&sess) {
+                continue;
+            }
+            if !sess.ready && !sess.started {
+                println!("[Getting ready for session {}]", sess.id);
+                if request::session_ready(client, sess.id.clone()).await {
+                    ready_sessions.insert(sess.id.clone(), sess);
+                } else {
+                    println!("Couldn't flag ourselves as ready!");
+                }
+            } else {
+                println!("[Catch up ready session: {}]", sess.id);
+                ready_sessions.insert(sess.id.clone(), sess);
+            }
+        }
+    }
+}
+
+async fn handle_sessions(client: Arc<Client>, max_games: usize) -> Vec<Session> {
     println!("[Sessions handler loop]");
     let sessions = request::sessions(&client).await;
     let active_sessions = sessions.into_iter().filter(|sess| !sess.ended).collect::<Vec<Session>>();
@@ -185,7 +206,7 @@ async fn handle_sessions(client: Arc<Client>) -> Vec<Session> {
 
     let mut dropped = Vec::new();
 
-    if active_sessions.len() > MAX_GAMES {
+    if active_sessions.len() > max_games {
         println!("Too many sessions, pruning the most recent ones...");
         for sess in active_sessions.iter() {
             if !sess.started {
@@ -196,7 +217,7 @@ async fn handle_sessions(client: Arc<Client>) -> Vec<Session> {
                 }
             }
 
-            if dropped.len() >= active_sessions.len() - MAX_GAMES {
+            if dropped.len() >= active_sessions.len() - max_games {
                 break;
             }
         }
@@ -205,21 +226,140 @@ async fn handle_sessions(client: Arc<Client>) -> Vec<Session> {
     active_sessions.into_iter().filter(|x| dropped.iter().find(|d| x.id == **d).is_none()).collect()
 }
 
-async fn handle_session(client: Arc<Client>, white: bool, mut session: Session) {
-    let mut interval = time::interval(time::Duration::from_secs(PING_INTERVAL));
+/**
+    Plays the session until it ends, polling it every `config.ping_interval`: answers the draw offers, plays the bot's turns and resigns hopeless positions.
+    Once the session has ended, its game is saved to `config.archive` and its result is appended to `config.results`.
+**/
+async fn handle_session(client: Arc<Client>, config: Arc<Config>, white: bool, mut session: Session) -> SessionReturnType {
+    let mut interval = time::interval(config.ping_interval());
+    let settings = config.search_for(&session.variant);
+    let mut history = archive::History::new(&session);
+    // Turns (`present`) whose draw offer was answered: the offer may still be flagged after the bot declined it
+    let mut answered_draws = HashSet::new();
     println!("[Session handler: {}]", session.id);
     loop {
         interval.tick().await;
 
         match request::session(&client, session.id.clone()).await {
             Some(s) => session = s,
-            None => println!("Couldn't get session {}!", session.id),
+            None => {
+                println!("Couldn't get session {}!", session.id);
+                continue;
+            }
         }
+        history.update(&session.game);
+
+        if session.ended {
+            println!("[Session {} ended: winner: {:?} ({:?})]", session.id, session.winner, session.win_cause);
+            if history.record.is_none() {
+                println!("Couldn't follow the movesets of session {}; only its last position is saved", session.id);
+            }
+            let raw = request::session_json(&client, session.id.clone()).await;
+            match archive::save(&config.archive, &session, &history, raw.as_deref()) {
+                Ok(path) => println!("[Session {} saved to {}]", session.id, path.display()),
+                Err(err) => println!("Couldn't save session {} to {}: {}", session.id, config.archive, err),
+            }
+            let result = results::GameResult::new(&session, &config.username, white);
+            if let Err(err) = results::append(&config.results, &result) {
+                println!("Couldn't write the result of session {} to {}: {}", session.id, config.results, err);
+            }
+            return;
+        }
+
+        let our_turn = session.player == white;
+        let present = session.game.info.present;
+        let draw_offer = session.offer_draw && !answered_draws.contains(&present);
+        // Draw offers are answered out of the search's score, so the search is also run on the opponent's turn if they offered one
+        if !our_turn && !draw_offer {
+            continue;
+        }
+
+        let duration = move_duration(&session.format, &settings);
+        // The search blocks its thread, so it is run outside of the runtime's workers
+        let game = std::mem::replace(&mut session.game, Game::new(0, 0));
+        let (game, result) = task::spawn_blocking(move || {
+            let result = search(&game, duration, &settings);
+            (game, result)
+        }).await.expect("The search panicked!");
+
+        let (moveset, score) = match result {
+            Some(result) => result,
+            None => {
+                println!("No moveset found in session {}!", session.id);
+                continue;
+            }
+        };
+        // The scores are positive when white is ahead
+        let score = score.map(|score| if white { score } else { -score });
+
+        if draw_offer {
+            let accept = score.map(|score| score < config.draw_threshold).unwrap_or(false);
+            println!("[Session {}: {} the draw offer (score: {:?})]", session.id, if accept { "accepting" } else { "declining" }, score);
+            if !request::session_draw(&client, session.id.clone(), accept).await {
+                println!("Couldn't answer the draw offer in session {}!", session.id);
+            } else {
+                answered_draws.insert(present);
+                if accept {
+                    continue;
+                }
+            }
+        }
+
+        if !our_turn {
+            continue;
+        }
+
+        if score.map(|score| score < config.resign_threshold).unwrap_or(false) {
+            println!("[Session {}: resigning (score: {:?})]", session.id, score);
+            if request::forfeit_session(&client, session.id.clone()).await.is_none() {
+                println!("Couldn't resign in session {}!", session.id);
+            }
+            continue;
+        }
+
+        println!("[Session {}: playing {:?}]", session.id, moveset);
+        let moves = match request::export_moveset(&game, &moveset) {
+            Some(moves) => moves,
+            None => {
+                println!("Couldn't convert moveset {:?}!", moveset);
+                continue;
+            }
+        };
+        let mut sent = true;
+        for mv in moves {
+            if !request::session_move(&client, session.id.clone(), mv).await {
+                println!("Couldn't send a move in session {}!", session.id);
+                sent = false;
+                break;
+            }
+        }
+        if sent && !request::session_submit(&client, session.id.clone()).await {
+            println!("Couldn't submit the moves in session {}!", session.id);
+        }
+    }
+}
+
+/**
+    Looks for the best moveset of `game` within `duration` with the given settings, and returns it along with its score (positive if white is ahead).
+    Falls back to the first legal moveset, without a score, if the search didn't give any result in time.
+**/
+fn search(game: &Game, duration: Duration, settings: &SearchConfig) -> Option<(Vec<Move>, Option<f32>)> {
+    let tt = TranspositionTable::new(settings.tt_size);
+    match iddfs::iddfs_bfs(game, settings.max_ms, settings.bucket_size, settings.pool_size, settings.threads, duration, &tt) {
+        Some((node, score)) => Some((node.0, Some(score))),
+        None => legal_movesets(game, &game.info, &vec![], 0, settings.max_ms).next().map(|node| (node.0, None)),
+    }
+}
 
-        if session.player == white {
-            // do them moves
-            // send the moves
+/// Returns the time that the search may take on each turn, out of the session's time control (written as `<minutes>+<increment in seconds>`)
+fn move_duration(format: &str, settings: &SearchConfig) -> Duration {
+    let mut parts = format.split('+').map(|x| x.trim().parse::<f64>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(minutes)), Some(Ok(increment))) if minutes.is_finite() && increment.is_finite() && minutes >= 0.0 && increment >= 0.0 => {
+            let seconds = (minutes * 60.0 / settings.expected_turns + increment) * settings.time_margin;
+            Duration::from_secs_f64(seconds.max(settings.min_move_time))
         }
+        _ => Duration::from_secs_f64(settings.default_move_time),
     }
 }
 
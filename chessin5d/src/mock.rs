@@ -0,0 +1,529 @@
+/*
+    In-process mock of the chessin5d server, for the tests of the bot.
+
+    It serves the endpoints used by `request` (`/register`, `/login`, `/sessions`, `/sessions/new` and the per-session
+    `/ready`, `/remove`, `/forfeit`, `/draw`, `/move` and `/submit`) on a local port, out of an in-memory state that the tests can look at and alter.
+    The other player of each session is scripted (see `Opponent`); the games are checked and played with `chess5dlib`.
+
+    The sessions are served in the format that `request::sessions::SessionRaw` reads, which the tests compare to a session in that format
+    (see `fixtures/session_standard.json`). The bodies of `/move` (a `MoveRaw`, including its `realEnd` and `coordinate` fields),
+    `/submit` and `/draw` (`{id, accept}`) are assumed rather than taken from the live API: the mock accepts what `request` sends,
+    so the tests only check the bot's side of these endpoints against itself.
+
+    Requests with no known token are made on behalf of the bot's user.
+*/
+
+use super::now;
+use super::request::sessions::*;
+use chess5dlib::{game::*, moves::*, notation, replay, status::{self, Status}, variants::Variant};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use roy::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// How the bot's opponent plays on its turns
+#[derive(Debug, Clone)]
+pub enum Opponent {
+    /// Plays the given movesets (in the 5dpgn notation) in order, then forfeits; it also forfeits if one of them is illegal
+    Script(VecDeque<String>),
+    /// Forfeits as soon as it has to play
+    Forfeit,
+    /// Never plays; the test ends the session
+    Idle,
+}
+
+#[derive(Debug)]
+pub struct MockSession {
+    pub id: String,
+    pub host: String,
+    pub white: Option<String>,
+    pub black: Option<String>,
+    pub variant: String,
+    pub format: String,
+    pub ready: bool,
+    /// Whether the opponent offered a draw, which the bot has yet to answer
+    pub offer_draw: bool,
+    pub started: bool,
+    /// Creation date of the session until it starts
+    pub start_date: u128,
+    pub ended: bool,
+    pub end_date: u128,
+    pub winner: Option<String>,
+    pub win_cause: Option<String>,
+    pub game: Game,
+    /// Moves sent by the player to move, until they submit them
+    pub pending: Vec<Move>,
+    /// Plays for the player of the session who isn't the bot
+    pub opponent: Opponent,
+}
+
+#[derive(Debug, Default)]
+pub struct MockState {
+    /// The bot's user
+    pub username: String,
+    /// Passwords of the registered users
+    pub users: HashMap<String, String>,
+    /// Users of the tokens handed out by `/register` and `/login`
+    pub tokens: HashMap<String, String>,
+    pub sessions: Vec<MockSession>,
+    /// Every request received, as `METHOD /path`
+    pub log: Vec<String>,
+    next_id: usize,
+}
+
+pub struct MockServer {
+    pub addr: SocketAddr,
+    pub state: Arc<Mutex<MockState>>,
+}
+
+impl MockServer {
+    /// Starts a mock server on a free local port; `username` is registered, and is the user of the requests without a known token
+    pub fn start(username: &str) -> Self {
+        let mut state = MockState {
+            username: String::from(username),
+            ..MockState::default()
+        };
+        state.users.insert(String::from(username), String::new());
+        let state = Arc::new(Mutex::new(state));
+
+        let make_service = {
+            let state = Arc::clone(&state);
+            make_service_fn(move |_connection| {
+                let state = Arc::clone(&state);
+                async move { Ok::<_, Infallible>(service_fn(move |req| handle(Arc::clone(&state), req))) }
+            })
+        };
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(async move {
+            if let Err(err) = server.await {
+                eprintln!("Mock server error: {}", err);
+            }
+        });
+
+        MockServer { addr, state }
+    }
+
+    pub fn hostname(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Returns a client logged in as the bot's user
+    pub fn client(&self) -> Arc<Client> {
+        let token = self.state.lock().unwrap().new_token(None);
+        Arc::new(Client::new_auth(self.hostname(), Box::leak(token.into_boxed_str())))
+    }
+
+    /**
+        Adds a session hosted by `host`, who plays white if `host_white` is true; the other side is left open.
+        The session is played from `game`; the player who isn't the bot is played by `opponent`.
+        Returns the id of the session.
+    **/
+    pub fn add_session(&self, host: &str, host_white: bool, game: Game, opponent: Opponent) -> String {
+        self.state.lock().unwrap().add_session(host, host_white, game, opponent)
+    }
+
+    /// Starts the session `id`; the opponent plays right away if it is their turn
+    pub fn start_session(&self, id: &str) {
+        let mut state = self.state.lock().unwrap();
+        let username = state.username.clone();
+        if let Some(session) = state.session_mut(id) {
+            session.started = true;
+            session.start_date = now();
+            session.opponent_turn(&username);
+        }
+    }
+
+    /// Calls `f` on the session `id`, if it exists
+    pub fn with_session<T, F: FnOnce(&mut MockSession) -> T>(&self, id: &str, f: F) -> Option<T> {
+        self.state.lock().unwrap().session_mut(id).map(f)
+    }
+
+    /// Returns the number of requests received on `path` with `method`
+    pub fn requests(&self, method: &str, path: &str) -> usize {
+        let request = format!("{} {}", method, path);
+        self.state.lock().unwrap().log.iter().filter(|r| **r == request).count()
+    }
+}
+
+impl MockState {
+    fn add_session(&mut self, host: &str, host_white: bool, game: Game, opponent: Opponent) -> String {
+        self.next_id += 1;
+        let id = format!("session-{}", self.next_id);
+        let host = String::from(host);
+        self.sessions.push(MockSession {
+            id: id.clone(),
+            white: if host_white { Some(host.clone()) } else { None },
+            black: if host_white { None } else { Some(host.clone()) },
+            host,
+            variant: String::from("Standard"),
+            format: String::from("0+1"),
+            ready: false,
+            offer_draw: false,
+            started: false,
+            start_date: now(),
+            ended: false,
+            end_date: 0,
+            winner: None,
+            win_cause: None,
+            game,
+            pending: Vec::new(),
+            opponent,
+        });
+        id
+    }
+
+    fn session_mut(&mut self, id: &str) -> Option<&mut MockSession> {
+        self.sessions.iter_mut().find(|s| s.id == id)
+    }
+
+    /// Returns a new token for `user` (the bot's user by default)
+    fn new_token(&mut self, user: Option<&str>) -> String {
+        let user = String::from(user.unwrap_or(&self.username));
+        let token = format!("token-{}-{}", user, self.tokens.len());
+        self.tokens.insert(token.clone(), user);
+        token
+    }
+
+    /// Returns the user making a request, out of its bearer token
+    fn user(&self, headers: &HeaderMap) -> String {
+        headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split_whitespace().last())
+            .and_then(|token| self.tokens.get(token))
+            .unwrap_or(&self.username)
+            .clone()
+    }
+}
+
+impl MockSession {
+    /// Returns the name of the player to move
+    pub fn player_to_move(&self) -> Option<&String> {
+        if self.game.info.active_player {
+            self.white.as_ref()
+        } else {
+            self.black.as_ref()
+        }
+    }
+
+    /// Returns the name of the player of `white`'s pieces
+    fn player(&self, white: bool) -> Option<String> {
+        if white {
+            self.white.clone()
+        } else {
+            self.black.clone()
+        }
+    }
+
+    /// Ends the game, won by `winner` (a draw if `None`)
+    pub fn end(&mut self, winner: Option<String>, cause: &str) {
+        self.ended = true;
+        self.end_date = now();
+        self.winner = winner;
+        self.win_cause = Some(String::from(cause));
+    }
+
+    /// Ends the game if the player to move has been checkmated or stalemated; returns whether or not the game goes on
+    fn check_status(&mut self) -> bool {
+        match status::game_status(&self.game) {
+            Status::Ongoing => true,
+            Status::Checkmate(white) => {
+                let winner = self.player(white);
+                self.end(winner, "checkmate");
+                false
+            }
+            Status::Stalemate => {
+                self.end(None, "stalemate");
+                false
+            }
+        }
+    }
+
+    /// Lets the scripted opponent play, if the game is going on and it is their turn; `username` is the bot's user
+    fn opponent_turn(&mut self, username: &str) {
+        match self.player_to_move() {
+            Some(player) if self.started && !self.ended && player != username => {}
+            _ => return,
+        }
+
+        match &mut self.opponent {
+            Opponent::Forfeit => {
+                let winner = self.player(!self.game.info.active_player);
+                self.end(winner, "forfeit");
+            }
+            Opponent::Idle => {}
+            Opponent::Script(script) => {
+                let played = match script.pop_front() {
+                    Some(raw) => match notation::parse_moveset(&self.game, &raw) {
+                        Ok(moveset) => replay::play_moveset(&mut self.game, &moveset).map_err(|err| err.to_string()),
+                        Err(err) => Err(format!("{:?}", err)),
+                    }
+                    .map_err(|err| eprintln!("The opponent's moveset {} is illegal: {}", raw, err))
+                    .is_ok(),
+                    None => false,
+                };
+                if played {
+                    self.check_status();
+                } else {
+                    let winner = self.player(!self.game.info.active_player);
+                    self.end(winner, "forfeit");
+                }
+            }
+        }
+    }
+
+    /// Finds the move of the player to move matching `raw`
+    fn import_move(&self, raw: &MoveRaw) -> Option<Move> {
+        let even = self.game.info.even_initial_timelines;
+        let position = |pos: &PositionRaw| {
+            (
+                parse_timeline_index(pos.timeline, even),
+                parse_time(pos.turn as usize, &pos.player),
+                pos.file as u8 - 1,
+                pos.rank as u8 - 1,
+            )
+        };
+        let src = position(&raw.start);
+        let board = self.game.get_board(src.0, src.1)?;
+
+        probable_moves(&self.game, board, &vec![]).into_iter().find(|mv| {
+            mv.src == src
+                && match &raw.castling {
+                    Some(castling) => mv.castle && mv.dst == position(&castling.start),
+                    None => {
+                        !mv.castle
+                            && mv.dst == position(&raw.end)
+                            && mv.promotion.and_then(export_piece_name).map(|(name, _)| name) == raw.promotion.as_deref()
+                    }
+                }
+        })
+    }
+
+    fn export(&self) -> SessionRaw {
+        let game = &self.game;
+        let even = game.info.even_initial_timelines;
+        let mut timelines = game.timelines.values().collect::<Vec<_>>();
+        timelines.sort_by_key(|tl| tl.index);
+
+        SessionRaw {
+            id: self.id.clone(),
+            host: self.host.clone(),
+            white: self.white.clone(),
+            black: self.black.clone(),
+            variant: self.variant.clone(),
+            format: self.format.clone(),
+            ranked: false,
+            ready: self.ready,
+            offerDraw: self.offer_draw,
+            started: self.started,
+            startDate: self.start_date,
+            ended: self.ended,
+            endDate: self.end_date,
+            archiveDate: 0,
+            player: String::from(export_player_color(game.info.active_player)),
+            winner: self.winner.clone(),
+            winCause: self.win_cause.clone(),
+            board: BoardRaw {
+                action: (game.info.present / 2 + 1) as usize,
+                player: String::from(export_player_color(game.info.active_player)),
+                width: Some(game.width as usize),
+                height: Some(game.height as usize),
+                timelines: timelines
+                    .into_iter()
+                    .map(|tl| TimelineRaw {
+                        timeline: export_timeline_index(tl.index, even),
+                        active: game.info.is_timeline_active(tl.index),
+                        present: tl.states.last().map(|b| b.t == game.info.present).unwrap_or(false),
+                        turns: tl.states.iter().map(|board| export_board(board, even)).collect(),
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+/// Converts a board to the server's format; the pieces are never flagged as having moved, as the bot doesn't read it
+fn export_board(board: &Board, even: bool) -> TurnRaw {
+    let mut pieces = Vec::new();
+    for y in 0..board.height {
+        for x in 0..board.width {
+            if let Some((name, white)) = export_piece_name(board.get_unsafe(x, y)) {
+                pieces.push(PieceRaw {
+                    position: export_position((board.l, board.t, x, y), even, board.width),
+                    piece: String::from(name),
+                    player: String::from(export_player_color(white)),
+                    hasMoved: false,
+                });
+            }
+        }
+    }
+
+    TurnRaw {
+        turn: board.t.div_euclid(2) as usize,
+        player: String::from(export_player_color(board.active_player())),
+        pieces,
+        width: Some(board.width as usize),
+        height: Some(board.height as usize),
+    }
+}
+
+#[derive(Deserialize)]
+struct UserBody {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct NewSessionBody {
+    player: String,
+}
+
+#[derive(Deserialize)]
+struct DrawBody {
+    accept: bool,
+}
+
+#[derive(Deserialize)]
+struct MoveBody {
+    #[serde(rename = "move")]
+    mv: MoveRaw,
+}
+
+async fn handle(state: Arc<Mutex<MockState>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body).await.map(|bytes| bytes.to_vec()).unwrap_or_default();
+
+    let mut state = state.lock().unwrap();
+    state.log.push(format!("{} {}", parts.method, parts.uri.path()));
+    let user = state.user(&parts.headers);
+    let path = parts.uri.path().trim_matches('/').split('/').collect::<Vec<_>>();
+
+    Ok(match (&parts.method, path.as_slice()) {
+        (&Method::POST, ["register"]) => match serde_json::from_slice::<UserBody>(&body) {
+            Ok(body) if !state.users.contains_key(&body.username) => {
+                state.users.insert(body.username.clone(), body.password);
+                reply(StatusCode::OK, state.new_token(Some(&body.username)))
+            }
+            Ok(_) => reply(StatusCode::BAD_REQUEST, "Username already taken"),
+            Err(err) => reply(StatusCode::BAD_REQUEST, err.to_string()),
+        },
+        (&Method::POST, ["login"]) => match serde_json::from_slice::<UserBody>(&body) {
+            Ok(body) if state.users.get(&body.username) == Some(&body.password) => {
+                reply(StatusCode::OK, state.new_token(Some(&body.username)))
+            }
+            Ok(_) => reply(StatusCode::UNAUTHORIZED, "Wrong username or password"),
+            Err(err) => reply(StatusCode::BAD_REQUEST, err.to_string()),
+        },
+        (&Method::GET, ["sessions"]) => json(&state.sessions.iter().map(|s| s.export()).collect::<Vec<_>>()),
+        (&Method::POST, ["sessions", "new"]) => match serde_json::from_slice::<NewSessionBody>(&body) {
+            Ok(body) => {
+                let game = Variant::Standard.game();
+                let id = state.add_session(&user, body.player != "black", game, Opponent::Script(VecDeque::new()));
+                json(&state.session_mut(&id).unwrap().export())
+            }
+            Err(err) => reply(StatusCode::BAD_REQUEST, err.to_string()),
+        },
+        (&Method::GET, ["sessions", id]) => match state.session_mut(id) {
+            Some(session) => json(&session.export()),
+            None => reply(StatusCode::NOT_FOUND, "No such session"),
+        },
+        (&Method::POST, ["sessions", id, action]) => {
+            let username = state.username.clone();
+            match state.sessions.iter().position(|s| s.id == *id) {
+                Some(index) if *action == "remove" => {
+                    let session = &state.sessions[index];
+                    if session.host != user {
+                        reply(StatusCode::FORBIDDEN, "Only the host may remove the session")
+                    } else if session.started {
+                        reply(StatusCode::BAD_REQUEST, "The session has started")
+                    } else {
+                        state.sessions.remove(index);
+                        reply(StatusCode::OK, "")
+                    }
+                }
+                Some(index) => session_action(&mut state.sessions[index], &user, &username, action, &body),
+                None => reply(StatusCode::NOT_FOUND, "No such session"),
+            }
+        }
+        _ => reply(StatusCode::NOT_FOUND, "Not found"),
+    })
+}
+
+/// Handles the `ready`, `forfeit`, `draw`, `move` and `submit` requests of `user` on `session`; `username` is the bot's user
+fn session_action(session: &mut MockSession, user: &str, username: &str, action: &str, body: &[u8]) -> Response<Body> {
+    let playing = session.white.as_deref() == Some(user) || session.black.as_deref() == Some(user);
+
+    match action {
+        "ready" => {
+            if session.started {
+                return reply(StatusCode::BAD_REQUEST, "The session has started");
+            }
+            if !playing {
+                if session.white.is_none() {
+                    session.white = Some(String::from(user));
+                } else if session.black.is_none() {
+                    session.black = Some(String::from(user));
+                } else {
+                    return reply(StatusCode::BAD_REQUEST, "The session is full");
+                }
+            }
+            session.ready = true;
+        }
+        "forfeit" => {
+            if !playing || !session.started || session.ended {
+                return reply(StatusCode::BAD_REQUEST, "Not playing in the session");
+            }
+            let winner = if session.white.as_deref() == Some(user) { session.black.clone() } else { session.white.clone() };
+            session.end(winner, "forfeit");
+        }
+        "draw" => {
+            if !playing || !session.offer_draw || session.ended {
+                return reply(StatusCode::BAD_REQUEST, "No draw offer to answer");
+            }
+            match serde_json::from_slice::<DrawBody>(body) {
+                Ok(body) if body.accept => session.end(None, "draw"),
+                Ok(_) => session.offer_draw = false,
+                Err(err) => return reply(StatusCode::BAD_REQUEST, err.to_string()),
+            }
+        }
+        "move" | "submit" => {
+            if !session.started || session.ended || session.player_to_move().map(|p| p.as_str()) != Some(user) {
+                return reply(StatusCode::BAD_REQUEST, "Not your turn");
+            }
+            if action == "move" {
+                let mv = match serde_json::from_slice::<MoveBody>(body) {
+                    Ok(body) => session.import_move(&body.mv),
+                    Err(err) => return reply(StatusCode::BAD_REQUEST, err.to_string()),
+                };
+                match mv {
+                    Some(mv) => session.pending.push(mv),
+                    None => return reply(StatusCode::BAD_REQUEST, "Illegal move"),
+                }
+            } else {
+                let moveset = std::mem::take(&mut session.pending);
+                if let Err(err) = replay::play_moveset(&mut session.game, &moveset) {
+                    return reply(StatusCode::BAD_REQUEST, err.to_string());
+                }
+                if session.check_status() {
+                    session.opponent_turn(username);
+                }
+            }
+        }
+        _ => return reply(StatusCode::NOT_FOUND, "Not found"),
+    }
+
+    json(&session.export())
+}
+
+fn reply<T: Into<Body>>(status: StatusCode, body: T) -> Response<Body> {
+    Response::builder().status(status).body(body.into()).unwrap()
+}
+
+fn json<T: Serialize>(value: &T) -> Response<Body> {
+    reply(StatusCode::OK, serde_json::to_string(value).unwrap())
+}
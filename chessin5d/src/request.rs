@@ -1,7 +1,8 @@
 use roy::Client;
 use super::{Config, Color, Session};
 use serde::{Deserialize, Serialize};
-use chess5dlib::game::*;
+use chess5dlib::{game::*, moves::Move, variants::from_boards};
+use std::convert::{TryFrom, TryInto};
 use std::time::{Instant, Duration};
 use std::sync::{Arc, Mutex};
 use tokio::time::delay_for;
@@ -104,7 +105,7 @@ pub async fn new_session(client: &Client, color: Color) -> Option<Session> {
             match res.text().await.ok() {
                 Some(raw_json) => {
                     let raw_obj: sessions::SessionRaw = serde_json::from_str(&raw_json).ok()?;
-                    return Some(raw_obj.into());
+                    return read_session(raw_obj);
                 }
                 None => {},
             }
@@ -124,7 +125,7 @@ pub async fn sessions(client: &Client) -> Vec<Session> {
             match res.text().await.ok() {
                 Some(raw_json) => {
                     let raw_vec: Option<Vec<sessions::SessionRaw>> = serde_json::from_str::<Vec<sessions::SessionRaw>>(&raw_json).ok();
-                    return raw_vec.unwrap_or(vec![]).into_iter().map(|raw| raw.into()).collect();
+                    return raw_vec.unwrap_or(vec![]).into_iter().filter_map(read_session).collect();
                 }
                 None => {},
             }
@@ -135,19 +136,32 @@ pub async fn sessions(client: &Client) -> Vec<Session> {
 }
 
 pub async fn session(client: &Client, id: String) -> Option<Session> {
+    let raw_json = session_json(client, id).await?;
+    let raw_obj: sessions::SessionRaw = serde_json::from_str(&raw_json).ok()?;
+    read_session(raw_obj)
+}
+
+/// Converts a session sent by the server; the sessions whose boards can't be read are reported and left out
+fn read_session(raw: sessions::SessionRaw) -> Option<Session> {
+    let id = raw.id.clone();
+    match Session::try_from(raw) {
+        Ok(session) => Some(session),
+        Err(err) => {
+            eprintln!("Couldn't read session {}: {}", id, err);
+            None
+        }
+    }
+}
+
+/// Returns the session `id`, as the server's JSON
+pub async fn session_json(client: &Client, id: String) -> Option<String> {
     ratelimit!(Duration::new(0, 250 * 1000000));
 
     let res = client.get(&format!("/sessions/{}", id), false).await;
 
     if let Some(res) = res {
         if res.status().is_success() {
-            match res.text().await.ok() {
-                Some(raw_json) => {
-                    let raw_obj: sessions::SessionRaw = serde_json::from_str(&raw_json).ok()?;
-                    return Some(raw_obj.into());
-                }
-                None => {},
-            }
+            return res.text().await.ok();
         }
     }
 
@@ -169,7 +183,7 @@ pub async fn forfeit_session(client: &Client, id: String) -> Option<Session> {
             match res.text().await.ok() {
                 Some(raw_json) => {
                     let raw_obj: sessions::SessionRaw = serde_json::from_str(&raw_json).ok()?;
-                    return Some(raw_obj.into());
+                    return read_session(raw_obj);
                 }
                 None => {},
             }
@@ -221,74 +235,149 @@ pub async fn remove_session(client: &Client, id: String) -> bool {
     }
 }
 
+pub async fn session_move(client: &Client, id: String, mv: sessions::MoveRaw) -> bool {
+    ratelimit!(Duration::new(0, 250 * 1000000));
+
+    #[derive(Serialize, Debug)]
+    struct SessionMoveBody {
+        pub id: String,
+        #[serde(rename = "move")]
+        pub mv: sessions::MoveRaw,
+    };
+
+    let res = client.post(&format!("/sessions/{}/move", id), SessionMoveBody {id, mv}).await;
+
+    if let Some(res) = res {
+        let x = res.status().is_success();
+        if !x {
+            eprintln!("{:?}", res.text().await);
+        }
+        x
+    } else {
+        false
+    }
+}
+
+pub async fn session_submit(client: &Client, id: String) -> bool {
+    ratelimit!(Duration::new(0, 250 * 1000000));
+
+    #[derive(Serialize, Debug)]
+    struct SessionSubmitBody {
+        pub id: String
+    };
+
+    let res = client.post(&format!("/sessions/{}/submit", id), SessionSubmitBody {id}).await;
+
+    if let Some(res) = res {
+        let x = res.status().is_success();
+        if !x {
+            eprintln!("{:?}", res.text().await);
+        }
+        x
+    } else {
+        false
+    }
+}
+
+/// Accepts or declines the draw offer of the session `id`
+pub async fn session_draw(client: &Client, id: String, accept: bool) -> bool {
+    ratelimit!(Duration::new(0, 250 * 1000000));
+
+    #[derive(Serialize, Debug)]
+    struct SessionDrawBody {
+        pub id: String,
+        pub accept: bool,
+    };
+
+    let res = client.post(&format!("/sessions/{}/draw", id), SessionDrawBody {id, accept}).await;
+
+    if let Some(res) = res {
+        let x = res.status().is_success();
+        if !x {
+            eprintln!("{:?}", res.text().await);
+        }
+        x
+    } else {
+        false
+    }
+}
+
+pub use sessions::{export_moveset, MoveRaw};
+
+/// The server's representation of the sessions, and their conversion from and to the structures of `chess5dlib`
 #[allow(dead_code)]
-mod sessions {
+pub mod sessions {
     use super::*;
 
     #[derive(Serialize, Deserialize, Debug, Clone)]
     #[allow(non_snake_case)]
     pub struct SessionRaw {
-        id: String,
-        host: String,
-        white: Option<String>,
-        black: Option<String>,
-        variant: String,
-        format: String,
-        ranked: bool,
-        ready: bool,
-        offerDraw: bool,
-        started: bool,
-        startDate: u128,
-        ended: bool,
-        endDate: u128,
-        archiveDate: usize,
-        player: String,
-        winner: Option<String>,
-        winCause: Option<String>,
-        board: BoardRaw,
-    }
-
-    impl Into<Session> for SessionRaw {
-        fn into(self) -> Session {
-            let mut game = Game::new(self.board.width(), self.board.height());
-            game.info.active_player = parse_player_color(&self.board.player);
-            for tl in &self.board.timelines {
-                game.timelines.push(
-                    BoardTimelinePair(&self.board, &tl).into()
-                );
+        pub id: String,
+        pub host: String,
+        pub white: Option<String>,
+        pub black: Option<String>,
+        pub variant: String,
+        pub format: String,
+        pub ranked: bool,
+        pub ready: bool,
+        pub offerDraw: bool,
+        pub started: bool,
+        pub startDate: u128,
+        pub ended: bool,
+        pub endDate: u128,
+        pub archiveDate: usize,
+        pub player: String,
+        pub winner: Option<String>,
+        pub winCause: Option<String>,
+        pub board: BoardRaw,
+    }
+
+    /// Reads the boards of a session; fails with a description of the first piece that can't be placed on its board
+    impl TryFrom<SessionRaw> for Session {
+        type Error = String;
+
+        fn try_from(raw: SessionRaw) -> Result<Self, String> {
+            // Games with an even amount of starting timelines number them -0.5 and 0.5
+            let even_initial_timelines = raw.board.timelines.iter().any(|tl| tl.timeline.abs() == 0.5);
+            let mut boards = Vec::new();
+            for tl in &raw.board.timelines {
+                let mut tl_boards: Vec<Board> = BoardTimelinePair(&raw.board, tl, even_initial_timelines).try_into()?;
+                boards.append(&mut tl_boards);
             }
-            Session {
-                id: self.id,
-                host: self.host,
-                white: self.white,
-                black: self.black,
-                variant: self.variant,
-                format: self.format,
-                ranked: self.ranked,
-                ready: self.ready,
-                offer_draw: self.offerDraw,
-                started: self.started,
-                start_date: self.startDate,
-                ended: self.ended,
-                end_date: self.endDate,
-                archive_date: self.archiveDate,
-                player: self.player == "white",
-                winner: self.winner,
-                win_cause: self.winCause,
-                width: self.board.width(),
-                height: self.board.height(),
+            let mut game = from_boards(&boards, even_initial_timelines);
+            game.info.active_player = parse_player_color(&raw.board.player);
+            Ok(Session {
+                id: raw.id,
+                host: raw.host,
+                white: raw.white,
+                black: raw.black,
+                variant: raw.variant,
+                format: raw.format,
+                ranked: raw.ranked,
+                ready: raw.ready,
+                offer_draw: raw.offerDraw,
+                started: raw.started,
+                start_date: raw.startDate,
+                ended: raw.ended,
+                end_date: raw.endDate,
+                archive_date: raw.archiveDate,
+                player: raw.player == "white",
+                winner: raw.winner,
+                win_cause: raw.winCause,
+                width: raw.board.width(),
+                height: raw.board.height(),
                 game,
-            }
+            })
         }
     }
 
     #[derive(Serialize, Deserialize, Debug, Clone)]
     #[allow(non_snake_case)]
-    struct BoardRaw {
+    pub struct BoardRaw {
         pub action: usize,
         pub player: String,
-        width: Option<usize>,
-        height: Option<usize>,
+        pub width: Option<usize>,
+        pub height: Option<usize>,
         pub timelines: Vec<TimelineRaw>,
     }
 
@@ -304,40 +393,42 @@ mod sessions {
 
     #[derive(Serialize, Deserialize, Debug, Clone)]
     #[allow(non_snake_case)]
-    struct TimelineRaw {
+    pub struct TimelineRaw {
         pub timeline: f32,
         pub active: bool,
         pub present: bool,
         pub turns: Vec<TurnRaw>,
     }
 
-    struct BoardTimelinePair<'a>(&'a BoardRaw, &'a TimelineRaw);
+    /// A timeline along with its game and whether or not the game has an even amount of starting timelines
+    struct BoardTimelinePair<'a>(&'a BoardRaw, &'a TimelineRaw, bool);
 
-    impl<'a> Into<Timeline> for BoardTimelinePair<'a> {
-        fn into(self) -> Timeline {
-            // Come on, rust
-            let (board, tl) = (self.0, self.1);
-            let mut res = Timeline::new(tl.timeline, board.width(), board.height(), 0, None);
-
-            if tl.turns.len() > 0 {
-                res.begins_at = (tl.turns[0].turn as isize) * 2 + (if parse_player_color(&tl.turns[0].player) {0} else {1});
-            }
+    impl<'a> TryFrom<BoardTimelinePair<'a>> for Vec<Board> {
+        type Error = String;
 
-            for (index, turn) in tl.turns.iter().enumerate() {
-                res.states.push(TurnTriple(res.begins_at + index as isize, tl.timeline, turn).into())
-            }
-            res
+        fn try_from(pair: BoardTimelinePair<'a>) -> Result<Self, String> {
+            // Come on, rust
+            let (board, tl, even) = (pair.0, pair.1, pair.2);
+            let l = parse_timeline_index(tl.timeline, even);
+
+            tl.turns
+                .iter()
+                .map(|turn| {
+                    let t = parse_time(turn.turn, &turn.player);
+                    TurnTriple(t, l, turn, board).try_into()
+                })
+                .collect()
         }
     }
 
     #[derive(Serialize, Deserialize, Debug, Clone)]
     #[allow(non_snake_case)]
-    struct TurnRaw {
+    pub struct TurnRaw {
         pub turn: usize,
         pub player: String,
         pub pieces: Vec<PieceRaw>,
-        width: Option<usize>,
-        height: Option<usize>,
+        pub width: Option<usize>,
+        pub height: Option<usize>,
     }
 
     impl TurnRaw {
@@ -352,43 +443,176 @@ mod sessions {
 
     #[derive(Serialize, Deserialize, Debug, Clone)]
     #[allow(non_snake_case)]
-    struct PieceRaw {
+    pub struct PieceRaw {
         pub position: PositionRaw,
         pub piece: String,
         pub player: String,
         pub hasMoved: bool,
     }
 
+    /// A square of the game; `turn`, `player` and `coordinate` are only given for the moves
     #[derive(Serialize, Deserialize, Debug, Clone)]
     #[allow(non_snake_case)]
-    struct PositionRaw {
+    pub struct PositionRaw {
         pub timeline: f32,
+        #[serde(default)]
+        pub turn: isize,
+        #[serde(default)]
+        pub player: String,
+        #[serde(default)]
+        pub coordinate: usize,
         pub rank: usize,
         pub file: usize,
     }
 
-    struct TurnTriple<'a>(isize, f32, &'a TurnRaw);
+    /// A move, as the server expects it
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[allow(non_snake_case)]
+    pub struct MoveRaw {
+        pub promotion: Option<String>,
+        pub enPassant: Option<PositionRaw>,
+        pub castling: Option<CastlingRaw>,
+        pub start: PositionRaw,
+        pub end: PositionRaw,
+        /// Where the piece ends up: differs from `end` when the move creates a new timeline
+        pub realEnd: PositionRaw,
+        pub player: String,
+    }
+
+    /// The move of the rook while castling
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[allow(non_snake_case)]
+    pub struct CastlingRaw {
+        pub start: PositionRaw,
+        pub end: PositionRaw,
+        pub realEnd: PositionRaw,
+    }
+
+    /// A board's time coordinate, timeline index and turn, along with its game
+    struct TurnTriple<'a>(isize, i32, &'a TurnRaw, &'a BoardRaw);
+
+    impl<'a> TryFrom<TurnTriple<'a>> for Board {
+        type Error = String;
 
-    impl<'a> Into<Board> for TurnTriple<'a> {
-        fn into(self) -> Board {
-            let (t, l, turn) = (self.0, self.1, self.2);
-            let mut res = Board::new(t, l, turn.width(), turn.height());
+        fn try_from(triple: TurnTriple<'a>) -> Result<Self, String> {
+            let (t, l, turn, board) = (triple.0, triple.1, triple.2, triple.3);
+            let mut res = Board::new(t, l, turn.width.unwrap_or(board.width()) as u8, turn.height.unwrap_or(board.height()) as u8);
 
             for piece in &turn.pieces {
-                res.set(piece.position.file - 1, piece.position.rank - 1, parse_piece_name(&piece.piece, parse_player_color(&piece.player))).unwrap();
+                let (file, rank) = (piece.position.file, piece.position.rank);
+                let kind = parse_piece_name(&piece.piece, parse_player_color(&piece.player))
+                    .ok_or_else(|| format!("unknown piece \"{}\" on turn {}", piece.piece, turn.turn))?;
+                // Files and ranks start at 1
+                let square = match (u8::try_from(file), u8::try_from(rank)) {
+                    (Ok(x), Ok(y)) if x > 0 && y > 0 => res.set(x - 1, y - 1, kind).ok(),
+                    _ => None,
+                };
+                if square.is_none() {
+                    return Err(format!("square {}/{} of turn {} is outside of the board", file, rank, turn.turn));
+                }
             }
 
-            res
+            Ok(res)
+        }
+    }
+
+    /**
+        Converts the moves of `moveset` to the server's format; null moves are left out.
+        The moves are played out one after the other, to know on which timeline the pieces travelling back in time end up.
+    **/
+    pub fn export_moveset(game: &Game, moveset: &[Move]) -> Option<Vec<MoveRaw>> {
+        let even = game.info.even_initial_timelines;
+        let width = game.width;
+        let mut info = game.info.clone();
+        let mut boards: Vec<Board> = Vec::new();
+        let mut res = Vec::with_capacity(moveset.len());
+
+        for mv in moveset.iter().filter(|mv| !mv.noop) {
+            let (new_info, mut new_boards) = mv.generate_vboards(game, &info, &vec![], &boards)?;
+            // The board created on another timeline than the source board's, if any, is where the piece lands
+            let real_l = new_boards.iter().find(|b| b.l != mv.src.0).map(|b| b.l).unwrap_or(mv.dst.0);
+
+            let (end, castling) = if mv.castle {
+                let y = mv.src.3;
+                let (king_x, rook_x) = if mv.castle_long { (2, 3) } else { (width - 2, width - 3) };
+                let rook_end = export_position((mv.src.0, mv.src.1, rook_x, y), even, width);
+                (
+                    export_position((mv.src.0, mv.src.1, king_x, y), even, width),
+                    Some(CastlingRaw {
+                        start: export_position(mv.dst, even, width),
+                        end: rook_end.clone(),
+                        realEnd: rook_end,
+                    }),
+                )
+            } else {
+                (export_position(mv.dst, even, width), None)
+            };
+            let real_end = PositionRaw {
+                timeline: export_timeline_index(real_l, even),
+                ..end.clone()
+            };
+
+            res.push(MoveRaw {
+                promotion: mv.promotion.and_then(export_piece_name).map(|(name, _)| String::from(name)),
+                enPassant: mv.en_passant.map(|(x, y)| export_position((mv.dst.0, mv.dst.1, x, y), even, width)),
+                castling,
+                start: export_position(mv.src, even, width),
+                end,
+                realEnd: real_end,
+                player: String::from(export_player_color(mv.src.1 % 2 == 0)),
+            });
+
+            boards.append(&mut new_boards);
+            info = new_info;
+        }
+
+        Some(res)
+    }
+
+    pub fn export_position(pos: (i32, isize, u8, u8), even: bool, width: u8) -> PositionRaw {
+        PositionRaw {
+            timeline: export_timeline_index(pos.0, even),
+            turn: pos.1.div_euclid(2),
+            player: String::from(export_player_color(pos.1.rem_euclid(2) == 0)),
+            coordinate: pos.3 as usize * width as usize + pos.2 as usize,
+            rank: pos.3 as usize + 1,
+            file: pos.2 as usize + 1,
         }
     }
 
     #[inline]
-    fn parse_player_color(color: &str) -> bool {
+    pub fn parse_player_color(color: &str) -> bool {
         color == "white"
     }
 
-    fn parse_piece_name(name: &str, white: bool) -> Piece {
-        match (name, white) {
+    /// Converts a turn number, counted from 0 like the server does, and the player to move to a time coordinate (`2 * turn` for white)
+    #[inline]
+    pub fn parse_time(turn: usize, player: &str) -> isize {
+        (turn as isize) * 2 + if parse_player_color(player) { 0 } else { 1 }
+    }
+
+    /// Converts a timeline index; with an even amount of starting timelines, -0.5 and 0.5 become -1 and 0 and the negative timelines are shifted by one
+    pub fn parse_timeline_index(raw: f32, even: bool) -> i32 {
+        if even && raw < 0.0 {
+            (raw.ceil() - 1.0) as i32
+        } else {
+            raw.floor() as i32
+        }
+    }
+
+    /// Inverse of `parse_timeline_index`
+    pub fn export_timeline_index(l: i32, even: bool) -> f32 {
+        match (even, l) {
+            (true, 0) => 0.5,
+            (true, -1) => -0.5,
+            (true, l) if l < 0 => (l + 1) as f32,
+            (_, l) => l as f32,
+        }
+    }
+
+    /// Converts the server's name of a piece; the pawns are unnamed
+    pub fn parse_piece_name(name: &str, white: bool) -> Option<Piece> {
+        Some(match (name, white) {
             ("", true) => Piece::PawnW,
             ("", false) => Piece::PawnB,
             ("N", true) => Piece::KnightW,
@@ -401,18 +625,24 @@ mod sessions {
             ("Q", false) => Piece::QueenB,
             ("P", true) => Piece::PrincessW,
             ("P", false) => Piece::PrincessB,
+            ("W", true) => Piece::BrawnW,
+            ("W", false) => Piece::BrawnB,
             ("K", true) => Piece::KingW,
             ("K", false) => Piece::KingB,
             ("U", true) => Piece::UnicornW,
             ("U", false) => Piece::UnicornB,
             ("D", true) => Piece::DragonW,
             ("D", false) => Piece::DragonB,
-            _ => Piece::Blank,
-        }
+            ("C", true) => Piece::CommonKingW,
+            ("C", false) => Piece::CommonKingB,
+            ("Y", true) => Piece::RoyalQueenW,
+            ("Y", false) => Piece::RoyalQueenB,
+            _ => return None,
+        })
     }
 
     #[inline]
-    fn export_player_color(white: bool) -> &'static str {
+    pub fn export_player_color(white: bool) -> &'static str {
         if white {
             "white"
         } else {
@@ -420,7 +650,7 @@ mod sessions {
         }
     }
 
-    fn export_piece_name(piece: Piece) -> Option<(&'static str, bool)> {
+    pub fn export_piece_name(piece: Piece) -> Option<(&'static str, bool)> {
         match piece {
             Piece::PawnW => Some(("", true)),
             Piece::PawnB => Some(("", false)),
@@ -434,13 +664,19 @@ mod sessions {
             Piece::QueenB => Some(("Q", false)),
             Piece::PrincessW => Some(("P", true)),
             Piece::PrincessB => Some(("P", false)),
+            Piece::BrawnW => Some(("W", true)),
+            Piece::BrawnB => Some(("W", false)),
             Piece::KingW => Some(("K", true)),
             Piece::KingB => Some(("K", false)),
             Piece::UnicornW => Some(("U", true)),
             Piece::UnicornB => Some(("U", false)),
             Piece::DragonW => Some(("D", true)),
             Piece::DragonB => Some(("D", false)),
-            _ => None,
+            Piece::CommonKingW => Some(("C", true)),
+            Piece::CommonKingB => Some(("C", false)),
+            Piece::RoyalQueenW => Some(("Y", true)),
+            Piece::RoyalQueenB => Some(("Y", false)),
+            Piece::Blank => None,
         }
     }
 
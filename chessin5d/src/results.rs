@@ -0,0 +1,54 @@
+/*
+    Results of the bot's games: once a session ends, a record of it is appended to the results file (see `Config::results`),
+    one JSON object per line.
+*/
+
+use super::Session;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Win,
+    Loss,
+    Draw,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GameResult {
+    /// Id of the session
+    pub id: String,
+    pub opponent: Option<String>,
+    /// The bot's colour, `"white"` or `"black"`
+    pub color: String,
+    pub variant: String,
+    pub outcome: Outcome,
+    /// Why the game ended (checkmate, forfeit, draw, etc.), as given by the server
+    pub cause: Option<String>,
+}
+
+impl GameResult {
+    /// Builds the result of the ended `session`, from the point of view of `username`, who played white if `white` is true
+    pub fn new(session: &Session, username: &str, white: bool) -> Self {
+        GameResult {
+            id: session.id.clone(),
+            opponent: if white { session.black.clone() } else { session.white.clone() },
+            color: String::from(if white { "white" } else { "black" }),
+            variant: session.variant.clone(),
+            outcome: match &session.winner {
+                Some(winner) if winner == username => Outcome::Win,
+                Some(_) => Outcome::Loss,
+                None => Outcome::Draw,
+            },
+            cause: session.win_cause.clone(),
+        }
+    }
+}
+
+/// Appends `result` to the file at `path`, creating it if needed
+pub fn append(path: &str, result: &GameResult) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(result)?)
+}
@@ -0,0 +1,438 @@
+/*
+    Tests of the bot's session lifecycle (getting ready, starting, playing and pruning sessions), against the mock server of `mock`,
+    and of the validation of its configuration.
+*/
+
+use super::*;
+use chess5dlib::{notation, variants::Variant};
+use mock::{MockServer, Opponent};
+use std::convert::TryFrom;
+
+const USERNAME: &str = "chess5dtoolsbot";
+const OPPONENT: &str = "opponent";
+/// Time given to the bot to play out a session
+const GAME_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The bot's state between two calls to `poll_sessions`
+struct Bot {
+    client: Arc<Client>,
+    config: Arc<Config>,
+    ready_sessions: HashMap<String, Session>,
+    started_sessions: HashMap<String, JoinHandle<SessionReturnType>>,
+}
+
+impl Bot {
+    fn new(mock: &MockServer) -> Self {
+        Bot::with_config(mock, config(mock))
+    }
+
+    fn with_config(mock: &MockServer, config: Config) -> Self {
+        Bot {
+            client: mock.client(),
+            config: Arc::new(config),
+            ready_sessions: HashMap::new(),
+            started_sessions: HashMap::new(),
+        }
+    }
+
+    async fn poll(&mut self) {
+        poll_sessions(&self.client, &self.config, &mut self.ready_sessions, &mut self.started_sessions).await;
+    }
+
+    /// Waits for the handler of the session `id` to finish, and returns the result of the game that it wrote to `config.results`
+    async fn finish(&mut self, id: &str) -> results::GameResult {
+        let handle = self.started_sessions.remove(id).expect("The session wasn't started");
+        time::timeout(GAME_TIMEOUT, handle)
+            .await
+            .expect("The session didn't end in time")
+            .expect("The session handler panicked");
+        std::fs::read_to_string(&self.config.results)
+            .expect("Couldn't read the results")
+            .lines()
+            .rev()
+            .map(|line| serde_json::from_str::<results::GameResult>(line).expect("Couldn't parse a result"))
+            .find(|result| result.id == id)
+            .expect("The session handler didn't write a result")
+    }
+}
+
+impl Drop for Bot {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.config.results).ok();
+        std::fs::remove_dir_all(&self.config.archive).ok();
+    }
+}
+
+/**
+    The bot's configuration: the defaults, polling every 100ms and never resigning nor accepting draws.
+    The results and the archive are written to the temporary directory, under names unique to `mock`.
+**/
+fn config(mock: &MockServer) -> Config {
+    let raw = format!("Config(username: \"{}\", password: \"\", bio: \"\", fullname: \"\", hostname: \"{}\", ping_interval: 0.1)", USERNAME, mock.hostname());
+    Config {
+        resign_threshold: f32::NEG_INFINITY,
+        draw_threshold: f32::NEG_INFINITY,
+        results: std::env::temp_dir().join(format!("chess5dtoolsbot-results-{}.jsonl", mock.addr.port())).to_string_lossy().into_owned(),
+        archive: std::env::temp_dir().join(format!("chess5dtoolsbot-archive-{}", mock.addr.port())).to_string_lossy().into_owned(),
+        ..ron::from_str::<Config>(&raw).expect("Couldn't parse the configuration")
+    }
+}
+
+fn standard() -> Game {
+    Variant::Standard.game()
+}
+
+fn post(id: &str, action: &str) -> String {
+    format!("/sessions/{}/{}", id, action)
+}
+
+/// Waits until `condition` holds, checking it every 50ms; panics with `message` after `GAME_TIMEOUT`
+async fn wait_until<F: Fn() -> bool>(condition: F, message: &str) {
+    let waiting = async {
+        while !condition() {
+            time::delay_for(Duration::from_millis(50)).await;
+        }
+    };
+    time::timeout(GAME_TIMEOUT, waiting).await.expect(message);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn gets_ready_then_plays_once_started() {
+    let mock = MockServer::start(USERNAME);
+    let mut bot = Bot::new(&mock);
+    let id = mock.add_session(OPPONENT, false, standard(), Opponent::Forfeit);
+
+    bot.poll().await;
+    assert_eq!(mock.requests("POST", &post(&id, "ready")), 1);
+    assert!(bot.ready_sessions.contains_key(&id));
+    assert_eq!(mock.with_session(&id, |s| s.white.clone()).unwrap().as_deref(), Some(USERNAME));
+
+    // Nothing happens until the host starts the session
+    bot.poll().await;
+    assert_eq!(mock.requests("POST", &post(&id, "ready")), 1);
+    assert!(bot.started_sessions.is_empty());
+
+    mock.start_session(&id);
+    bot.poll().await;
+    assert!(!bot.ready_sessions.contains_key(&id));
+    let result = bot.finish(&id).await;
+
+    assert!(mock.requests("POST", &post(&id, "move")) >= 1);
+    assert_eq!(mock.requests("POST", &post(&id, "submit")), 1);
+    mock.with_session(&id, |s| {
+        assert!(s.ended);
+        assert_eq!(s.winner.as_deref(), Some(USERNAME));
+        assert_eq!(s.win_cause.as_deref(), Some("forfeit"));
+    });
+
+    let expected = results::GameResult {
+        id: id.clone(),
+        opponent: Some(String::from(OPPONENT)),
+        color: String::from("white"),
+        variant: String::from("Standard"),
+        outcome: results::Outcome::Win,
+        cause: Some(String::from("forfeit")),
+    };
+    assert_eq!(result, expected);
+    let written = std::fs::read_to_string(&bot.config.results).expect("Couldn't read the results");
+    assert_eq!(serde_json::from_str::<results::GameResult>(written.trim()).unwrap(), expected);
+
+    // The ended session is forgotten
+    bot.poll().await;
+    assert!(bot.started_sessions.is_empty() && bot.ready_sessions.is_empty());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn plays_against_a_scripted_opponent() {
+    let mock = MockServer::start(USERNAME);
+    let mut bot = Bot::new(&mock);
+    let script = vec![String::from("(0T1)f3"), String::from("(0T2)g4")];
+    let id = mock.add_session(OPPONENT, true, standard(), Opponent::Script(script.into()));
+
+    bot.poll().await;
+    mock.start_session(&id);
+    bot.poll().await;
+    assert_eq!(bot.finish(&id).await.outcome, results::Outcome::Win);
+
+    // The opponent forfeits once it runs out of movesets, after the bot's second turn
+    assert_eq!(mock.requests("POST", &post(&id, "submit")), 2);
+    mock.with_session(&id, |s| {
+        assert_eq!(s.black.as_deref(), Some(USERNAME));
+        assert_eq!(s.winner.as_deref(), Some(USERNAME));
+        assert_eq!(s.win_cause.as_deref(), Some("forfeit"));
+    });
+
+    // The archived game replays to the session's final position
+    let archive = std::path::Path::new(&bot.config.archive);
+    let written = std::fs::read_to_string(archive.join(format!("{}.5dpgn", id))).expect("Couldn't read the archived game");
+    let record = notation::parse(&written).expect("Couldn't parse the archived game");
+    assert_eq!(record.turns.len(), 4);
+    assert_eq!(record.header("Black"), Some(USERNAME));
+    assert_eq!(record.header("Result"), Some("0-1"));
+    assert_eq!(record.header("Termination"), Some("forfeit"));
+    mock.with_session(&id, |s| assert_eq!(record.game.hash, s.game.hash));
+    assert!(archive.join(format!("{}.json", id)).exists());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn answers_draw_offers() {
+    let mock = MockServer::start(USERNAME);
+    let mut declining = Bot::new(&mock);
+    let declined = mock.add_session(OPPONENT, false, standard(), Opponent::Forfeit);
+    declining.poll().await;
+    mock.with_session(&declined, |s| s.offer_draw = true);
+    mock.start_session(&declined);
+    declining.poll().await;
+    assert_eq!(declining.finish(&declined).await.outcome, results::Outcome::Win);
+    assert_eq!(mock.requests("POST", &post(&declined, "draw")), 1);
+    assert_eq!(mock.requests("POST", &post(&declined, "submit")), 1);
+
+    let mut accepting = Bot::with_config(&mock, Config { draw_threshold: f32::INFINITY, ..config(&mock) });
+    let accepted = mock.add_session(OPPONENT, false, standard(), Opponent::Forfeit);
+    accepting.poll().await;
+    mock.with_session(&accepted, |s| s.offer_draw = true);
+    mock.start_session(&accepted);
+    accepting.poll().await;
+    let result = accepting.finish(&accepted).await;
+    assert_eq!(result.outcome, results::Outcome::Draw);
+    assert_eq!(result.cause.as_deref(), Some("draw"));
+    assert_eq!(mock.requests("POST", &post(&accepted, "submit")), 0);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn answers_each_draw_offer_once() {
+    let mock = MockServer::start(USERNAME);
+    let mut bot = Bot::new(&mock);
+    let id = mock.add_session(OPPONENT, true, standard(), Opponent::Idle);
+    bot.poll().await;
+    mock.with_session(&id, |s| s.offer_draw = true);
+    mock.start_session(&id);
+    bot.poll().await;
+
+    wait_until(|| mock.requests("POST", &post(&id, "draw")) == 1, "The draw offer wasn't answered").await;
+
+    // The offer stays flagged on the opponent's turn; the bot has already declined it, so it neither searches nor answers again
+    mock.with_session(&id, |s| s.offer_draw = true);
+    let path = format!("/sessions/{}", id);
+    let polls = mock.requests("GET", &path);
+    wait_until(|| mock.requests("GET", &path) >= polls + 3, "The bot stopped polling the session").await;
+    assert_eq!(mock.requests("POST", &post(&id, "draw")), 1);
+
+    mock.with_session(&id, |s| s.end(Some(String::from(USERNAME)), "forfeit"));
+    assert_eq!(bot.finish(&id).await.outcome, results::Outcome::Win);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn resigns_hopeless_positions() {
+    let mock = MockServer::start(USERNAME);
+    let mut bot = Bot::with_config(&mock, Config { resign_threshold: f32::INFINITY, ..config(&mock) });
+    let id = mock.add_session(OPPONENT, false, standard(), Opponent::Forfeit);
+
+    bot.poll().await;
+    mock.start_session(&id);
+    bot.poll().await;
+    let result = bot.finish(&id).await;
+
+    assert_eq!(result.outcome, results::Outcome::Loss);
+    assert_eq!(result.cause.as_deref(), Some("forfeit"));
+    assert_eq!(mock.requests("POST", &post(&id, "forfeit")), 1);
+    assert_eq!(mock.requests("POST", &post(&id, "move")), 0);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn removes_sessions_that_nobody_joined() {
+    let mock = MockServer::start(USERNAME);
+    let mut bot = Bot::new(&mock);
+    let stale_own = mock.add_session(USERNAME, true, standard(), Opponent::Forfeit);
+    let stale_other = mock.add_session(OPPONENT, true, standard(), Opponent::Forfeit);
+    let fresh_other = mock.add_session(OPPONENT, true, standard(), Opponent::Forfeit);
+    for id in &[&stale_own, &stale_other] {
+        mock.with_session(id, |s| s.start_date = now() - bot.config.new_game_timeout_ms() - 1000);
+    }
+
+    bot.poll().await;
+
+    assert!(mock.with_session(&stale_own, |_| ()).is_none());
+    assert_eq!(mock.requests("POST", &post(&stale_other, "remove")), 0);
+    assert_eq!(mock.requests("POST", &post(&stale_other, "ready")), 0);
+    assert_eq!(mock.with_session(&fresh_other, |s| s.ready), Some(true));
+    assert!(bot.ready_sessions.contains_key(&fresh_other));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn prunes_sessions_past_max_games() {
+    let mock = MockServer::start(USERNAME);
+    let mut bot = Bot::new(&mock);
+    let max_games = bot.config.max_games;
+    let ids = (0..=max_games)
+        .map(|_| mock.add_session(USERNAME, true, standard(), Opponent::Forfeit))
+        .collect::<Vec<_>>();
+
+    bot.poll().await;
+
+    assert_eq!(mock.state.lock().unwrap().sessions.len(), max_games);
+    assert!(mock.with_session(&ids[0], |_| ()).is_none());
+    assert_eq!(bot.ready_sessions.len(), max_games);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn joins_the_sessions_it_accepts() {
+    let mock = MockServer::start(USERNAME);
+    let mut bot = Bot::with_config(&mock, Config {
+        max_games: 8,
+        color: Color::White,
+        variants: vec![String::from("Very Small")],
+        formats: vec![String::from("10+5")],
+        ..config(&mock)
+    });
+    let session = |variant: &str, format: &str, host_white: bool| {
+        let id = mock.add_session(OPPONENT, host_white, standard(), Opponent::Forfeit);
+        mock.with_session(&id, |s| {
+            s.variant = String::from(variant);
+            s.format = String::from(format);
+        });
+        id
+    };
+    let accepted = session("Very Small", "10+5", false);
+    let wrong_variant = session("Standard", "10+5", false);
+    let wrong_format = session("Very Small", "0+1", false);
+    let wrong_color = session("Very Small", "10+5", true);
+    let own = mock.add_session(USERNAME, true, standard(), Opponent::Forfeit);
+
+    bot.poll().await;
+
+    assert!(bot.ready_sessions.contains_key(&accepted));
+    assert!(bot.ready_sessions.contains_key(&own));
+    for id in &[&wrong_variant, &wrong_format, &wrong_color] {
+        assert_eq!(mock.requests("POST", &post(id, "ready")), 0);
+        assert!(!bot.ready_sessions.contains_key(*id));
+    }
+}
+
+#[test]
+fn the_sample_config_is_valid() {
+    let config = ron::from_str::<Config>(include_str!("../config.ron.sample")).expect("Couldn't parse the sample configuration");
+    assert_eq!(config.validate(), Ok(()));
+    assert_eq!(config.search_for("Very Small").max_ms, config.variant_search["Very Small"].max_ms);
+    assert_eq!(config.search_for("Standard - Princess"), config.search);
+}
+
+#[test]
+fn rejects_invalid_configs() {
+    let valid = ron::from_str::<Config>(include_str!("../config.ron.sample")).unwrap();
+    let field = |config: Config| match config.validate() {
+        Err(config::ConfigError::Invalid(field, _)) => field,
+        res => panic!("The configuration was accepted: {:?}", res),
+    };
+
+    assert_eq!(field(Config { username: String::from("a bot"), ..valid.clone() }), "username");
+    assert_eq!(field(Config { hostname: String::from("server.chessin5d.net"), ..valid.clone() }), "hostname");
+    assert_eq!(field(Config { max_games: 0, ..valid.clone() }), "max_games");
+    assert_eq!(field(Config { ping_interval: -1.0, ..valid.clone() }), "ping_interval");
+    assert_eq!(field(Config { variants: vec![String::from(" ")], ..valid.clone() }), "variants");
+    assert!(Config { variants: vec![String::from("Very Large")], ..valid.clone() }.validate().is_ok());
+
+    let search = config::SearchConfig { threads: 0, ..Default::default() };
+    assert_eq!(field(Config { search, ..valid.clone() }), "search.threads");
+    let mut variant_search = HashMap::new();
+    variant_search.insert(String::from("Standard"), config::SearchConfig { time_margin: 1.5, ..Default::default() });
+    assert_eq!(field(Config { variant_search, ..valid }), "variant_search[\"Standard\"].time_margin");
+
+    assert!(matches!(Config::load("./no-such-config.ron"), Err(config::ConfigError::Io(..))));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn registers_and_logs_in() {
+    let mock = MockServer::start(USERNAME);
+    let config = Config {
+        username: String::from("newcomer"),
+        password: String::from("hunter2"),
+        ..config(&mock)
+    };
+    let client = Client::new(config.hostname.clone());
+
+    assert!(request::register(&client, &config).await.is_some());
+    assert!(request::login(&client, &config).await.is_some());
+    assert_eq!(mock.state.lock().unwrap().users.get("newcomer").map(|p| p.as_str()), Some("hunter2"));
+}
+
+/**
+    A session in the format of the server's `/sessions` endpoints, as read by `request::sessions::SessionRaw`: a Standard game after `1. e3`, the bot playing black.
+    The server counts the turns from 0, each turn holding a white and a black board.
+**/
+const STANDARD_SESSION: &str = include_str!("../fixtures/session_standard.json");
+
+#[test]
+fn reads_and_writes_the_server_format() {
+    let raw = serde_json::from_str::<request::sessions::SessionRaw>(STANDARD_SESSION).expect("Couldn't parse the session");
+    let session = Session::try_from(raw).expect("Couldn't read the session");
+    let game = &session.game;
+    assert!(!session.player);
+    assert_eq!(game.get_timeline(0).unwrap().states.iter().map(|b| b.t).collect::<Vec<_>>(), vec![0, 1]);
+    assert_eq!(game.get_board(0, 1).unwrap().get(4, 2), Some(Piece::PawnW));
+    assert_eq!(game.info.present, 1);
+
+    let moveset = notation::parse_moveset(game, "(0T1)Ng8f6").unwrap();
+    let exported = serde_json::to_value(request::export_moveset(game, &moveset).unwrap()).unwrap();
+    let position = |rank: usize, file: usize| {
+        serde_json::json!({"timeline": 0.0, "turn": 0, "player": "black", "coordinate": (rank - 1) * 8 + file - 1, "rank": rank, "file": file})
+    };
+    assert_eq!(
+        exported,
+        serde_json::json!([{
+            "promotion": null,
+            "enPassant": null,
+            "castling": null,
+            "start": position(8, 7),
+            "end": position(6, 6),
+            "realEnd": position(6, 6),
+            "player": "black",
+        }])
+    );
+}
+
+#[test]
+fn rejects_the_pieces_that_cant_be_read() {
+    // Edits white's king on the first board of the session
+    let read = |edit: &dyn Fn(&mut serde_json::Value)| {
+        let mut raw = serde_json::from_str::<serde_json::Value>(STANDARD_SESSION).unwrap();
+        edit(&mut raw["board"]["timelines"][0]["turns"][0]["pieces"][4]);
+        Session::try_from(serde_json::from_value::<request::sessions::SessionRaw>(raw).unwrap())
+    };
+
+    let session = read(&|piece| piece["piece"] = "C".into()).expect("Couldn't read the common king");
+    assert_eq!(session.game.get_board(0, 0).unwrap().get(4, 0), Some(Piece::CommonKingW));
+    assert_eq!(request::sessions::export_piece_name(Piece::RoyalQueenB), Some(("Y", false)));
+    assert_eq!(request::sessions::parse_piece_name("Y", false), Some(Piece::RoyalQueenB));
+
+    assert!(read(&|piece| piece["piece"] = "X".into()).is_err());
+    assert!(read(&|piece| piece["position"]["file"] = 0.into()).is_err());
+    assert!(read(&|piece| piece["position"]["rank"] = 9.into()).is_err());
+}
+
+/// Keeps what the bot reads of a session in the server's format: who is to move, and the timelines and pieces of the game
+fn game_of(raw: &str) -> serde_json::Value {
+    let session = serde_json::from_str::<serde_json::Value>(raw).expect("Couldn't parse the session");
+    let timelines = session["board"]["timelines"].as_array().unwrap().iter().map(|tl| {
+        let turns = tl["turns"].as_array().unwrap().iter().map(|turn| {
+            let mut pieces = turn["pieces"].as_array().unwrap().iter().map(|piece| {
+                let position = &piece["position"];
+                (position["rank"].as_u64(), position["file"].as_u64(), piece["piece"].clone(), piece["player"].clone())
+            }).collect::<Vec<_>>();
+            pieces.sort_by_key(|piece| (piece.0, piece.1));
+            serde_json::json!({"turn": turn["turn"], "player": turn["player"], "pieces": pieces})
+        }).collect::<Vec<_>>();
+        serde_json::json!({"timeline": tl["timeline"].as_f64(), "turns": turns})
+    }).collect::<Vec<_>>();
+    serde_json::json!({"player": session["player"], "board": {"player": session["board"]["player"], "timelines": timelines}})
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn the_mock_serves_sessions_in_the_server_format() {
+    let mock = MockServer::start(USERNAME);
+    let game = notation::parse("[Board \"Standard\"]\n\n1. (0T1)e3\n").unwrap().game;
+    let id = mock.add_session(OPPONENT, true, game, Opponent::Forfeit);
+
+    let served = request::session_json(&mock.client(), id).await.expect("Couldn't get the session");
+    assert_eq!(game_of(&served), game_of(STANDARD_SESSION));
+}
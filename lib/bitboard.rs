@@ -0,0 +1,148 @@
+/*
+    Bitboards of the boards that fit within 8x8 squares: the bit `x + 8 * y` of a bitboard stands for the `(x, y)` square.
+
+    The packed squares of these boards give away which squares either player occupies (see `Board::occupancy`), which lets
+    `moves::probable_moves_for` find the moves of knights, kings and sliders that stay on their board with a few lookups and bit operations,
+    instead of walking `moves::PERMUTATIONS` square by square. Moves going through other boards still take the generic path.
+*/
+
+pub type Bitboard = u64;
+
+/// Directions of the rays used by the sliders, as `(dx, dy)`; the first four ones go towards the higher bits
+const DIRECTIONS: [(isize, isize); 8] = [(1, 0), (0, 1), (1, 1), (-1, 1), (-1, 0), (0, -1), (-1, -1), (1, -1)];
+
+lazy_static! {
+    static ref KNIGHT_ATTACKS: [Bitboard; 64] = steps(&[(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)]);
+    static ref KING_ATTACKS: [Bitboard; 64] = steps(&[(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)]);
+    /// `RAYS[d][square]` holds the squares seen from `square` along `DIRECTIONS[d]` on an empty board
+    static ref RAYS: [[Bitboard; 64]; 8] = {
+        let mut res = [[0; 64]; 8];
+        for (d, &(dx, dy)) in DIRECTIONS.iter().enumerate() {
+            for (square, ray) in res[d].iter_mut().enumerate() {
+                let (mut x, mut y) = ((square % 8) as isize + dx, (square / 8) as isize + dy);
+                while in_bounds(x, y) {
+                    *ray |= bit(x as u8, y as u8);
+                    x += dx;
+                    y += dy;
+                }
+            }
+        }
+        res
+    };
+}
+
+/// The squares occupied by each player on a board, and the ones holding royal pieces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Occupancy {
+    pub white: Bitboard,
+    pub black: Bitboard,
+    pub royal: Bitboard,
+}
+
+impl Occupancy {
+    /// The squares occupied by either player
+    #[inline]
+    pub fn all(&self) -> Bitboard {
+        self.white | self.black
+    }
+
+    /// The squares occupied by `active_player`'s pieces
+    #[inline]
+    pub fn own(&self, active_player: bool) -> Bitboard {
+        if active_player {
+            self.white
+        } else {
+            self.black
+        }
+    }
+}
+
+/// Returns whether or not a board of the given size can be represented by bitboards
+#[inline]
+pub fn fits(width: u8, height: u8) -> bool {
+    width <= 8 && height <= 8
+}
+
+#[inline]
+pub fn bit(x: u8, y: u8) -> Bitboard {
+    1 << (x as u32 + 8 * y as u32)
+}
+
+/// Returns the squares that belong to a board of the given size
+#[inline]
+pub fn valid_squares(width: u8, height: u8) -> Bitboard {
+    let row = if width >= 8 { 0xff } else { (1 << width) - 1 };
+    (0..height.min(8)).fold(0, |acc, y| acc | row << (8 * y as u32))
+}
+
+/// Returns the squares that a knight on `(x, y)` can reach on its board
+#[inline]
+pub fn knight_attacks(x: u8, y: u8) -> Bitboard {
+    KNIGHT_ATTACKS[(x + 8 * y) as usize]
+}
+
+/// Returns the squares that a king on `(x, y)` can reach on its board
+#[inline]
+pub fn king_attacks(x: u8, y: u8) -> Bitboard {
+    KING_ATTACKS[(x + 8 * y) as usize]
+}
+
+/// Returns the squares that a rook on `(x, y)` can reach on its board, given the occupied squares; the first piece of each ray is included
+#[inline]
+pub fn rook_attacks(x: u8, y: u8, occupied: Bitboard) -> Bitboard {
+    ray_attacks(0, x, y, occupied) | ray_attacks(1, x, y, occupied) | ray_attacks(4, x, y, occupied) | ray_attacks(5, x, y, occupied)
+}
+
+/// Returns the squares that a bishop on `(x, y)` can reach on its board, given the occupied squares; the first piece of each ray is included
+#[inline]
+pub fn bishop_attacks(x: u8, y: u8, occupied: Bitboard) -> Bitboard {
+    ray_attacks(2, x, y, occupied) | ray_attacks(3, x, y, occupied) | ray_attacks(6, x, y, occupied) | ray_attacks(7, x, y, occupied)
+}
+
+/// Returns the `(x, y)` coordinates of the squares of `bitboard`, lowest bits first
+pub fn squares(mut bitboard: Bitboard) -> impl Iterator<Item = (u8, u8)> {
+    std::iter::from_fn(move || {
+        if bitboard == 0 {
+            None
+        } else {
+            let index = bitboard.trailing_zeros() as u8;
+            bitboard &= bitboard - 1;
+            Some((index % 8, index / 8))
+        }
+    })
+}
+
+/// Squares seen along the `direction`-th ray: the ray is cut right after its first occupied square
+#[inline]
+fn ray_attacks(direction: usize, x: u8, y: u8, occupied: Bitboard) -> Bitboard {
+    let ray = RAYS[direction][(x + 8 * y) as usize];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        ray
+    } else {
+        let first = if direction < 4 {
+            blockers.trailing_zeros()
+        } else {
+            63 - blockers.leading_zeros()
+        };
+        ray ^ RAYS[direction][first as usize]
+    }
+}
+
+fn steps(deltas: &[(isize, isize)]) -> [Bitboard; 64] {
+    let mut res = [0; 64];
+    for (square, attacks) in res.iter_mut().enumerate() {
+        for &(dx, dy) in deltas {
+            let (x, y) = ((square % 8) as isize + dx, (square / 8) as isize + dy);
+            if in_bounds(x, y) {
+                *attacks |= bit(x as u8, y as u8);
+            }
+        }
+    }
+    res
+}
+
+#[inline]
+fn in_bounds(x: isize, y: isize) -> bool {
+    (0..8).contains(&x) && (0..8).contains(&y)
+}
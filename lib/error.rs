@@ -0,0 +1,67 @@
+/*
+    Crate-wide error type.
+
+    The notation reader and the replay engine have their own, more detailed error types (`NotationError` and `ReplayError`);
+    they can be converted into `Error` so that callers only have to deal with one type.
+*/
+
+use crate::{notation::NotationError, replay::ReplayError};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A 5dchess-notation piece index that doesn't correspond to any piece
+    UnknownPiece(usize),
+    /// A timeline index that is neither an integer nor, with even initial timelines, a half-integer
+    InvalidTimeline(f32),
+    /// A board was appended at `(l, t)`, where there already is one
+    BoardCollision(i32, isize),
+    /// The board at `(l, t)` is needed but doesn't exist
+    MissingBoard(i32, isize),
+    /// The square `(x, y)` lies outside of the board
+    OutOfBounds(u8, u8),
+    /// The board at `(l, t)` doesn't have `width × height` squares
+    InvalidBoardSize(i32, isize),
+    /// The game doesn't contain any timeline
+    NoTimeline,
+    /// The JSON input couldn't be deserialized
+    Json(String),
+    /// A 5dpgn record couldn't be read
+    Notation(NotationError),
+    /// A list of movesets couldn't be replayed
+    Replay(ReplayError),
+}
+
+/// Shorthand for `Result<T, Error>`
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownPiece(raw) => write!(f, "unknown piece index: {}", raw),
+            Error::InvalidTimeline(raw) => write!(f, "invalid timeline index: {}", raw),
+            Error::BoardCollision(l, t) => write!(f, "there already is a board at {}/{}", l, t),
+            Error::MissingBoard(l, t) => write!(f, "there is no board at {}/{}", l, t),
+            Error::OutOfBounds(x, y) => write!(f, "square {}/{} is out of bounds", x, y),
+            Error::InvalidBoardSize(l, t) => write!(f, "board {}/{} has the wrong number of squares", l, t),
+            Error::NoTimeline => write!(f, "the game has no timeline"),
+            Error::Json(err) => write!(f, "invalid JSON: {}", err),
+            Error::Notation(err) => write!(f, "{}", err),
+            Error::Replay(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<NotationError> for Error {
+    fn from(err: NotationError) -> Self {
+        Error::Notation(err)
+    }
+}
+
+impl From<ReplayError> for Error {
+    fn from(err: ReplayError) -> Self {
+        Error::Replay(err)
+    }
+}
@@ -2,6 +2,7 @@
     Structures and functions related to the game's state.
 */
 
+use crate::{bitboard::Occupancy, error::{Error, Result}, squares::Squares, zobrist};
 use std::fmt;
 use std::convert::TryFrom;
 use std::collections::HashMap;
@@ -13,6 +14,23 @@ pub struct Game {
     pub width: u8,
     pub height: u8,
     pub info: GameInfo,
+    /// Journal of the committed movesets, most recent last
+    pub history: Vec<HistoryEntry>,
+    /// Undone movesets that can be committed again (the boards that were removed and the `GameInfo` that followed them), most recently undone last
+    pub redo_stack: Vec<(Vec<Board>, GameInfo)>,
+    /// Zobrist hash of the boards of the game (see `zobrist`); call `rehash` after modifying `timelines` by hand
+    pub hash: u64,
+}
+
+/// Records what committing a moveset changed in a `Game`, so that it can be undone
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    /// The `(l, t)` coordinates of the boards that were appended
+    pub boards: Vec<(i32, isize)>,
+    /// The timelines that were created
+    pub timelines: Vec<i32>,
+    /// The `GameInfo` before the moveset was committed
+    pub info: GameInfo,
 }
 
 /// Information about whose turn it is, where the present is and timeline priority
@@ -36,22 +54,31 @@ pub struct Timeline {
     pub emerges_from: Option<i32>,
 }
 
-/// Represents an in-game board (be it active or not)
+/**
+    Represents an in-game board (be it active or not).
+
+    The squares are stored compactly (see `squares`): boards of up to 64 squares are packed within the board itself, so that cloning one is cheap,
+    which lets the search states hold many boards.
+**/
 #[derive(Debug, Clone)]
 pub struct Board {
-    pub pieces: Vec<Piece>,
+    /// The squares of the board, row by row; modify them through `set`
+    pub squares: Squares,
     pub width: u8,
     pub height: u8,
     pub l: i32, // its timeline
     pub t: isize, // its time coordinate
-    pub king_w: Option<(u8, u8)>, // TODO: update if the king moves
+    pub king_w: Option<(u8, u8)>,
     pub king_b: Option<(u8, u8)>,
     pub castle_w: (bool, bool),
     pub castle_b: (bool, bool),
+    /// XOR of the `zobrist::square_key`s of the pieces; kept up to date by `set`
+    pub hash: u64,
 }
 
 /// Represents the contents of a board's square
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
 pub enum Piece {
     Blank,
 
@@ -64,6 +91,9 @@ pub enum Piece {
     UnicornW,
     DragonW,
     PrincessW,
+    BrawnW,
+    CommonKingW,
+    RoyalQueenW,
 
     KingB,
     QueenB,
@@ -74,6 +104,9 @@ pub enum Piece {
     UnicornB,
     DragonB,
     PrincessB,
+    BrawnB,
+    CommonKingB,
+    RoyalQueenB,
 }
 
 impl Game {
@@ -88,7 +121,10 @@ impl Game {
                 active_player: true,
                 min_timeline: 0,
                 max_timeline: 0,
-            }
+            },
+            history: Vec::new(),
+            redo_stack: Vec::new(),
+            hash: 0,
         }
     }
 }
@@ -106,10 +142,12 @@ impl Timeline {
     }
 }
 
-impl From<usize> for Piece {
+impl TryFrom<usize> for Piece {
+    type Error = Error;
+
     /// Converts 5dchess-notation piece indices into `Piece`s.
-    fn from(raw: usize) -> Self {
-        match raw {
+    fn try_from(raw: usize) -> Result<Self> {
+        Ok(match raw {
             0 => Piece::Blank,
             1 => Piece::PawnW,
             2 => Piece::KnightW,
@@ -120,6 +158,9 @@ impl From<usize> for Piece {
             7 => Piece::UnicornW,
             8 => Piece::DragonW,
             9 => Piece::PrincessW,
+            10 => Piece::BrawnW,
+            11 => Piece::CommonKingW,
+            12 => Piece::RoyalQueenW,
             33 => Piece::PawnB,
             34 => Piece::KnightB,
             35 => Piece::BishopB,
@@ -129,8 +170,11 @@ impl From<usize> for Piece {
             39 => Piece::UnicornB,
             40 => Piece::DragonB,
             41 => Piece::PrincessB,
-            _ => panic!("Invalid piece: {}", raw),
-        }
+            42 => Piece::BrawnB,
+            43 => Piece::CommonKingB,
+            44 => Piece::RoyalQueenB,
+            _ => return Err(Error::UnknownPiece(raw)),
+        })
     }
 }
 
@@ -148,6 +192,9 @@ impl From<Piece> for usize {
             Piece::UnicornW => 7,
             Piece::DragonW => 8,
             Piece::PrincessW => 9,
+            Piece::BrawnW => 10,
+            Piece::CommonKingW => 11,
+            Piece::RoyalQueenW => 12,
             Piece::PawnB => 33,
             Piece::KnightB => 34,
             Piece::BishopB => 35,
@@ -157,6 +204,9 @@ impl From<Piece> for usize {
             Piece::UnicornB => 39,
             Piece::DragonB => 40,
             Piece::PrincessB => 41,
+            Piece::BrawnB => 42,
+            Piece::CommonKingB => 43,
+            Piece::RoyalQueenB => 44,
         }
     }
 }
@@ -174,6 +224,9 @@ impl Piece {
             Piece::UnicornW | Piece::UnicornB => "U",
             Piece::DragonW | Piece::DragonB => "D",
             Piece::PrincessW | Piece::PrincessB => "S",
+            Piece::BrawnW | Piece::BrawnB => "W",
+            Piece::CommonKingW | Piece::CommonKingB => "C",
+            Piece::RoyalQueenW | Piece::RoyalQueenB => "Y",
         }
     }
 
@@ -198,7 +251,10 @@ impl Piece {
             | Piece::KingW
             | Piece::UnicornW
             | Piece::DragonW
-            | Piece::PrincessW => true,
+            | Piece::PrincessW
+            | Piece::BrawnW
+            | Piece::CommonKingW
+            | Piece::RoyalQueenW => true,
             _ => false,
         }
     }
@@ -215,7 +271,10 @@ impl Piece {
             | Piece::KingB
             | Piece::UnicornB
             | Piece::DragonB
-            | Piece::PrincessB => true,
+            | Piece::PrincessB
+            | Piece::BrawnB
+            | Piece::CommonKingB
+            | Piece::RoyalQueenB => true,
             _ => false,
         }
     }
@@ -247,6 +306,15 @@ impl Piece {
         }
     }
 
+    /// Returns whether or not that Piece is a `Piece::Brawn*` (a pawn that may also capture along the other axes)
+    #[inline]
+    pub fn is_brawn(&self) -> bool {
+        match &self {
+            Piece::BrawnW | Piece::BrawnB => true,
+            _ => false,
+        }
+    }
+
     /// Returns whether or not that Piece is a `Piece::Knight*`
     #[inline]
     pub fn is_knight(&self) -> bool {
@@ -301,6 +369,33 @@ impl Piece {
         }
     }
 
+    /// Returns whether or not that Piece is a `Piece::CommonKing*` (a king that isn't royal)
+    #[inline]
+    pub fn is_common_king(&self) -> bool {
+        match &self {
+            Piece::CommonKingW | Piece::CommonKingB => true,
+            _ => false,
+        }
+    }
+
+    /// Returns whether or not that Piece is a `Piece::RoyalQueen*` (a queen that is royal)
+    #[inline]
+    pub fn is_royal_queen(&self) -> bool {
+        match &self {
+            Piece::RoyalQueenW | Piece::RoyalQueenB => true,
+            _ => false,
+        }
+    }
+
+    /// Returns whether or not that Piece is royal, ie. whether or not it may not be left in check
+    #[inline]
+    pub fn is_royal(&self) -> bool {
+        match &self {
+            Piece::KingW | Piece::KingB | Piece::RoyalQueenW | Piece::RoyalQueenB => true,
+            _ => false,
+        }
+    }
+
     /**
         Whether or not the piece can move by `n` tiles in any direction until it is blocked.
     **/
@@ -313,12 +408,14 @@ impl Piece {
             | Piece::UnicornW
             | Piece::DragonW
             | Piece::PrincessW
+            | Piece::RoyalQueenW
             | Piece::BishopB
             | Piece::RookB
             | Piece::QueenB
             | Piece::UnicornB
             | Piece::DragonB
-            | Piece::PrincessB => true,
+            | Piece::PrincessB
+            | Piece::RoyalQueenB => true,
             _ => false,
         }
     }
@@ -356,6 +453,20 @@ impl Piece {
 }
 
 impl Game {
+    /// Recomputes `hash` from every board of the game
+    pub fn rehash(&mut self) {
+        self.hash = self
+            .timelines
+            .values()
+            .flat_map(|tl| tl.states.iter())
+            .fold(0, |hash, b| hash ^ b.zobrist());
+    }
+
+    /// Returns the Zobrist hash of the position: that of its boards and of its `GameInfo`
+    pub fn position_hash(&self) -> u64 {
+        self.hash ^ zobrist::info_key(&self.info)
+    }
+
     /// Returns whether or not there are +0/-0 timelines
     pub fn even_initial_timelines(&self) -> bool {
         self.info.even_initial_timelines
@@ -420,25 +531,103 @@ impl Game {
         // Assign to `mv` and `game`
 
         let boards = mv.generate_vboards(&game, &game.info, &vec![], &vec![]);
-        game.commit_moves(boards);
+        game.commit_moves(boards)?;
         ```
+
+        The changes are recorded in `history` and can be reverted with `undo`; committing clears the `redo_stack`.
+        Returns an error and leaves the game untouched if a board would overwrite an existing one or wouldn't follow the last board of its timeline.
     **/
-    pub fn commit_moves(&mut self, mut boards: Vec<Board>) {
+    pub fn commit_moves(&mut self, boards: Vec<Board>) -> Result<()> {
+        self.check_boards(&boards)?;
+        self.commit_checked_moves(boards);
+        Ok(())
+    }
+
+    /// Like `commit_moves`, for boards that already went through `check_boards` (see `replay::validate_moveset`)
+    pub(crate) fn commit_checked_moves(&mut self, boards: Vec<Board>) {
+        self.redo_stack.clear();
+        self.commit_boards(boards);
+    }
+
+    /**
+        Reverts the last committed moveset: removes the boards and timelines it added and restores the previous `GameInfo`.
+        The moveset is kept on the `redo_stack`. Returns false if there is nothing to undo.
+    **/
+    pub fn undo(&mut self) -> bool {
+        let entry = match self.history.pop() {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        let mut boards: Vec<Board> = Vec::with_capacity(entry.boards.len());
+        for (l, t) in entry.boards.iter().rev() {
+            if let Some(tl) = self.get_timeline_mut(*l) {
+                if tl.states.last().map(|b| b.t == *t).unwrap_or(false) {
+                    let board = tl.states.pop().unwrap();
+                    self.hash ^= board.zobrist();
+                    boards.push(board);
+                }
+            }
+        }
+        for l in entry.timelines.iter() {
+            self.timelines.remove(l);
+        }
+
+        self.redo_stack.push((boards, self.info));
+        self.info = entry.info;
+        true
+    }
+
+    /// Commits the last undone moveset again, restoring the `GameInfo` that followed it. Returns false if there is nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        match self.redo_stack.pop() {
+            Some((boards, info)) => {
+                if self.check_boards(&boards).is_err() {
+                    self.redo_stack.push((boards, info));
+                    return false;
+                }
+                self.commit_boards(boards);
+                self.info = info;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Checks that `boards` can be appended to their timelines
+    pub(crate) fn check_boards(&self, boards: &[Board]) -> Result<()> {
+        for b in boards {
+            if boards.iter().filter(|b2| b2.l == b.l && b2.t == b.t).count() > 1 {
+                return Err(Error::BoardCollision(b.l, b.t));
+            }
+            if let Some(tl) = self.get_timeline(b.l) {
+                if tl.get_board(b.t).is_some() {
+                    return Err(Error::BoardCollision(b.l, b.t));
+                }
+                if tl.states.last().map(|last| last.t + 1 != b.t).unwrap_or(false) {
+                    return Err(Error::MissingBoard(b.l, b.t - 1));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the boards and records a new `HistoryEntry`; `boards` must have gone through `check_boards`
+    fn commit_boards(&mut self, mut boards: Vec<Board>) {
+        let mut entry = HistoryEntry {
+            boards: Vec::with_capacity(boards.len()),
+            timelines: Vec::new(),
+            info: self.info,
+        };
         boards.sort_by_key(|b| b.t);
         boards.reverse();
         for b in boards.into_iter() {
+            entry.boards.push((b.l, b.t));
+            self.hash ^= b.zobrist();
             if let Some(tl) = self.get_timeline_mut(b.l) {
-                if tl.get_board(b.t).is_none() {
-                    tl.states.push(b)
-                } else {
-                    for (i, b2) in tl.states.iter().enumerate() {
-                        println!("> {}+{}={} : {}", tl.begins_at, i, tl.begins_at + i as isize, b2.t);
-                    }
-                    println!("{:?}", tl.get_board(b.t));
-                    println!("{:?}", b);
-                    panic!("Board already there: {}/{}", b.l, b.t);
-                }
+                tl.states.push(b);
             } else {
+                entry.timelines.push(b.l);
                 self.timelines.insert(b.l, Timeline {
                     index: b.l,
                     begins_at: b.t,
@@ -449,6 +638,7 @@ impl Game {
                 });
             }
         }
+        self.history.push(entry);
         self.info.active_player = !self.info.active_player;
     }
 }
@@ -494,7 +684,41 @@ impl Timeline {
 
     /// Returns the piece at `(t, x, y)` in this timeline, panics if the square does not exist. UB if that board's size is not equal to the timeline's own size
     pub fn get_unsafe<'a>(&'a self, t: isize, x: u8, y: u8) -> Piece {
-        self.states[(t - self.begins_at) as usize].pieces[(x + self.width * y) as usize]
+        self.states[(t - self.begins_at) as usize].squares.get(x, y)
+    }
+}
+
+impl GameInfo {
+    /**
+        Returns the indices of the lowest and highest active timelines.
+
+        A player may create one more timeline than their opponent did, on top of the initial ones, while keeping all of them active;
+        the timelines created past that are inactive until the opponent creates a timeline of their own, which reactivates one of them.
+        White creates timelines of positive index (`max_timeline` grows) and black of negative index (`min_timeline` decreases);
+        with even initial timelines, `-1` is the `-0` timeline.
+    **/
+    pub fn active_timelines(&self) -> (i32, i32) {
+        // Number of timelines created by white and black
+        let created_w = self.max_timeline;
+        let created_b = if self.even_initial_timelines {
+            -self.min_timeline - 1
+        } else {
+            -self.min_timeline
+        };
+
+        let highest = self.max_timeline.min(created_b + 1);
+        let lowest = if self.even_initial_timelines {
+            self.min_timeline.max(-created_w - 2)
+        } else {
+            self.min_timeline.max(-created_w - 1)
+        };
+        (lowest, highest)
+    }
+
+    /// Returns whether or not the timeline `l` is active
+    pub fn is_timeline_active(&self, l: i32) -> bool {
+        let (lowest, highest) = self.active_timelines();
+        l >= lowest && l <= highest
     }
 }
 
@@ -506,11 +730,12 @@ impl Board {
             l,
             width,
             height,
-            pieces: vec![Piece::Blank; (width as usize) * (height as usize)],
+            squares: Squares::new(width, height),
             king_w: None,
             king_b: None,
             castle_w: (false, false),
             castle_b: (false, false),
+            hash: 0,
         }
     }
 
@@ -519,28 +744,48 @@ impl Board {
         if x >= self.width || y >= self.height {
             None
         } else {
-            self.pieces.get((x + y * self.width) as usize).copied()
+            Some(self.get_unsafe(x, y))
         }
     }
 
     /// Returns the piece at `(x, y)`, panics if not found
     pub fn get_unsafe(&self, x: u8, y: u8) -> Piece {
-        self.pieces[(x + y * self.width) as usize]
+        self.squares.get(x, y)
+    }
+
+    /// Returns the pieces of the board, row by row
+    pub fn pieces(&self) -> impl Iterator<Item = Piece> + '_ {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| self.squares.get(x, y)))
+    }
+
+    /// Returns the squares occupied by each player, for boards that fit within 8x8 squares (see `bitboard`)
+    pub fn occupancy(&self) -> Occupancy {
+        self.squares.occupancy()
     }
 
     /// Sets the piece at `(x, y)`, returns `Ok` on success and `Err` if the square does not exist
-    pub fn set(&mut self, x: u8, y: u8, piece: Piece) -> Result<(), ()> {
+    pub fn set(&mut self, x: u8, y: u8, piece: Piece) -> Result<()> {
         if x >= self.width || y >= self.height {
-            Err(())
+            Err(Error::OutOfBounds(x, y))
         } else {
-            self.pieces[(x + y * self.width) as usize] = piece;
+            self.set_unsafe(x, y, piece);
             Ok(())
         }
     }
 
     /// Sets the piece at `(x, y)`, panics if the square does not exist
     pub fn set_unsafe(&mut self, x: u8, y: u8, piece: Piece) {
-        self.pieces[(x + y * self.width) as usize] = piece;
+        let previous = self.squares.get(x, y);
+        if previous == piece {
+            return;
+        }
+        self.squares.set(x, y, piece);
+        self.hash ^= zobrist::square_key(x, y, previous) ^ zobrist::square_key(x, y, piece);
+    }
+
+    /// Returns the Zobrist key of this board (see `zobrist::board_key`)
+    pub fn zobrist(&self) -> u64 {
+        zobrist::board_key(self)
     }
 
     /// Returns whose player's turn it is on this board
@@ -550,21 +795,7 @@ impl Board {
 
     /// Returns whether or not this board must be played on (does not check if it is the last board in its timeline)
     pub fn is_active(&self, info: &GameInfo) -> bool {
-        if info.even_initial_timelines {
-            self.t <= info.present
-                && if self.l < 0 {
-                    self.l >= -info.max_timeline - 2
-                } else {
-                    self.l <= -info.min_timeline + 1
-                }
-        } else {
-            self.t <= info.present
-                && if self.l < 0 {
-                    self.l >= -info.max_timeline - 1
-                } else {
-                    self.l <= -info.min_timeline + 1
-                }
-        }
+        self.t <= info.present && info.is_timeline_active(self.l)
     }
 }
 
@@ -584,6 +815,9 @@ impl fmt::Display for Piece {
                 Piece::UnicornW => "U",
                 Piece::DragonW => "D",
                 Piece::PrincessW => "S",
+                Piece::BrawnW => "W",
+                Piece::CommonKingW => "C",
+                Piece::RoyalQueenW => "Y",
                 Piece::PawnB => "p",
                 Piece::KnightB => "n",
                 Piece::BishopB => "b",
@@ -593,6 +827,9 @@ impl fmt::Display for Piece {
                 Piece::UnicornB => "u",
                 Piece::DragonB => "d",
                 Piece::PrincessB => "s",
+                Piece::BrawnB => "w",
+                Piece::CommonKingB => "c",
+                Piece::RoyalQueenB => "y",
             }
         )
     }
@@ -602,7 +839,7 @@ impl fmt::Display for Board {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for y in (0..self.height).rev() {
             for x in 0..self.width {
-                write!(f, "{}", self.pieces[(x + y * self.height) as usize])?;
+                write!(f, "{}", self.get_unsafe(x, y))?;
             }
             if y > 0 {
                 write!(f, "\n")?;
@@ -704,35 +941,37 @@ pub fn write_file(x: u8) -> char {
 }
 
 /**
-    Populates the castling rights of every board in the game; does so by induction (uses the `bubble_down` function)
+    Populates the castling rights of every board in the game; does so by induction (uses the `bubble_down` function).
+    The castling rights are part of the boards' Zobrist keys: `Game::rehash` should be called afterwards.
 **/
 pub fn populate_castling_rights(game: &mut Game) {
     // I apologize to code readers for the visual density of this function
 
     let width = game.width;
     let height = game.height;
+    // Initial timelines begin at the earliest time (which is before 0 in variants like "Turn Zero")
+    let begins_at = match game.timelines.values().map(|tl| tl.begins_at).min() {
+        Some(t) => t,
+        None => return,
+    };
     let timeline_indices: Vec<i32> = game
         .timelines
         .values()
-        .filter(|tl| tl.begins_at == 0)
+        .filter(|tl| tl.begins_at == begins_at)
         .map(|tl| tl.index.clone())
         .collect();
 
     for l in timeline_indices {
         // Extract white and black's position
         let (king_w, king_b) = {
-            let board = game.get_board(l, 0).unwrap();
+            let board = game.get_board(l, begins_at).unwrap();
             let kings_w: Vec<(usize, Piece)> = board
-                .pieces
-                .iter()
-                .copied()
+                .pieces()
                 .enumerate()
                 .filter(|(_i, p)| *p == Piece::KingW)
                 .collect();
             let kings_b: Vec<(usize, Piece)> = board
-                .pieces
-                .iter()
-                .copied()
+                .pieces()
                 .enumerate()
                 .filter(|(_i, p)| *p == Piece::KingB)
                 .collect();
@@ -747,21 +986,17 @@ pub fn populate_castling_rights(game: &mut Game) {
 
         // Get rook positions
         let (rook_w1, rook_w2, rook_b1, rook_b2) = {
-            let board = game.get_board(l, 0).unwrap();
+            let board = game.get_board(l, begins_at).unwrap();
 
             let rooks_w: Vec<(usize, Piece)> = board
-                .pieces
-                .iter()
-                .copied()
+                .pieces()
                 .enumerate()
                 .filter(|(i, p)| {
                     *p == Piece::RookW && *i / width as usize == king_w.map(|k| k.1).unwrap_or(height) as usize
                 })
                 .collect();
             let rooks_b: Vec<(usize, Piece)> = board
-                .pieces
-                .iter()
-                .copied()
+                .pieces()
                 .enumerate()
                 .filter(|(i, p)| {
                     *p == Piece::RookB && *i / width as usize == king_b.map(|k| k.1).unwrap_or(height) as usize
@@ -797,7 +1032,7 @@ pub fn populate_castling_rights(game: &mut Game) {
         bubble_down(
             game,
             l,
-            0,
+            begins_at,
             |board, mut last_state| {
                 if last_state.0 {
                     last_state.0 = rook_w1
@@ -866,3 +1101,37 @@ pub fn populate_castling_rights(game: &mut Game) {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{notation, replay};
+
+    /// Plays the turns of `raw` and undoes them one by one, checking the hash of the game along the way
+    fn undo_all(raw: &str) {
+        let record = notation::parse(raw).unwrap();
+        let mut game = record.initial_game();
+        let mut hashes = vec![game.hash];
+        for moveset in &record.turns {
+            replay::play_moveset(&mut game, moveset).unwrap();
+            hashes.push(game.hash);
+        }
+
+        while game.undo() {
+            hashes.pop();
+            assert_eq!(game.hash, *hashes.last().unwrap());
+            let hash = game.hash;
+            game.rehash();
+            assert_eq!(game.hash, hash);
+        }
+        assert_eq!(hashes.len(), 1);
+
+        while game.redo() {}
+        assert_eq!(game.hash, record.game.hash);
+    }
+
+    #[test]
+    fn undo_restores_the_hash() {
+        undo_all("[Board \"Standard\"]\n\n1. (0T1)Ng1f3 / (0T1)Ng8f6\n2. (0T2)Nf3>>(0T1)f5 / (1T1)Nb8c6\n3. (1T2)e3 / (0T2)e6 (1T2)e6\n");
+        undo_all("[Board \"Standard - Two Timelines\"]\n\n1. (-0T1)Ng1>(+0T1)g3 / (-0T1)Ng8f6 (+0T1)Ng8f6\n2. (-0T2)e3 (+0T2)e3\n");
+    }
+}
@@ -7,10 +7,20 @@ extern crate log;
 extern crate serde;
 extern crate serde_json;
 
+pub mod bitboard;
+pub mod error;
 pub mod game;
 pub mod moves;
 pub mod moveset;
 pub mod resolve;
+pub mod squares;
 pub mod tree;
+pub mod transposition;
 pub mod parse;
+pub mod perft;
+pub mod notation;
+pub mod replay;
+pub mod status;
+pub mod variants;
 pub mod vboard;
+pub mod zobrist;
@@ -1,5 +1,5 @@
 // Handles moves
-use super::{game::*, moveset::*, resolve::*};
+use super::{bitboard, game::*, moveset::*, resolve::*};
 use std::fmt;
 
 // Generate permutations for the basic, symmetric piece movements
@@ -79,6 +79,8 @@ pub struct Move {
     pub src_piece: Piece,
     pub dst_piece: Piece,
     pub noop: bool,
+    /// The piece that a pawn or brawn reaching the last rank turns into
+    pub promotion: Option<Piece>,
 }
 
 impl fmt::Debug for Move {
@@ -93,14 +95,14 @@ impl fmt::Debug for Move {
                     f,
                     "({}T{})O-O-O",
                     (self.src.0).to_string(),
-                    self.src.1 / 2 + 1
+                    self.src.1.div_euclid(2) + 1
                 )
             } else {
                 write!(
                     f,
                     "({}T{})O-O",
                     (self.src.0).to_string(),
-                    self.src.1 / 2 + 1
+                    self.src.1.div_euclid(2) + 1
                 )
             }
         } else {
@@ -111,7 +113,7 @@ impl fmt::Debug for Move {
                             f,
                             "({}T{}){}{}",
                             (self.src.0).to_string(),
-                            self.src.1 / 2 + 1,
+                            self.src.1.div_euclid(2) + 1,
                             write_file(self.dst.2),
                             (self.dst.3 + 1),
                         )
@@ -120,7 +122,7 @@ impl fmt::Debug for Move {
                             f,
                             "({}T{}){}x{}{}",
                             (self.src.0).to_string(),
-                            self.src.1 / 2 + 1,
+                            self.src.1.div_euclid(2) + 1,
                             write_file(self.src.2),
                             write_file(self.dst.2),
                             (self.dst.3 + 1),
@@ -132,7 +134,7 @@ impl fmt::Debug for Move {
                             f,
                             "({}T{}){}{}{}{}{}",
                             (self.src.0).to_string(),
-                            self.src.1 / 2 + 1,
+                            self.src.1.div_euclid(2) + 1,
                             self.src_piece.as_uppercase(),
                             write_file(self.src.2),
                             (self.src.3 + 1),
@@ -144,7 +146,7 @@ impl fmt::Debug for Move {
                             f,
                             "({}T{}){}{}{}x{}{}",
                             (self.src.0).to_string(),
-                            self.src.1 / 2 + 1,
+                            self.src.1.div_euclid(2) + 1,
                             self.src_piece.as_uppercase(),
                             write_file(self.src.2),
                             (self.src.3 + 1),
@@ -159,12 +161,12 @@ impl fmt::Debug for Move {
                         f,
                         "({}T{}){}{}{}>>({}T{}){}{}{}",
                         (self.src.0).to_string(),
-                        self.src.1 / 2 + 1,
+                        self.src.1.div_euclid(2) + 1,
                         self.src_piece.as_uppercase(),
                         write_file(self.src.2),
                         (self.src.3 + 1),
                         (self.dst.0).to_string(),
-                        self.dst.1 / 2 + 1,
+                        self.dst.1.div_euclid(2) + 1,
                         self.dst_piece,
                         write_file(self.dst.2),
                         (self.dst.3 + 1),
@@ -174,24 +176,28 @@ impl fmt::Debug for Move {
                         f,
                         "({}T{}){}{}{}>>x({}T{}){}{}{}",
                         (self.src.0).to_string(),
-                        self.src.1 / 2 + 1,
+                        self.src.1.div_euclid(2) + 1,
                         self.src_piece.as_uppercase(),
                         write_file(self.src.2),
                         (self.src.3 + 1),
                         (self.dst.0).to_string(),
-                        self.dst.1 / 2 + 1,
+                        self.dst.1.div_euclid(2) + 1,
                         self.dst_piece,
                         write_file(self.dst.2),
                         (self.dst.3 + 1),
                     )
                 }
             }
+        }?;
+        if let Some(piece) = self.promotion {
+            write!(f, "={}", piece.as_uppercase())?;
         }
+        Ok(())
     }
 }
 
 impl Move {
-    /// Creates a new normal move; extracts piece information from `game` and `virtual_boards`
+    /// Creates a new normal move; extracts piece information from `game` and `virtual_boards`. Pawns and brawns reaching the last rank promote to a queen
     pub fn new(
         src: (i32, isize, u8, u8),
         dst: (i32, isize, u8, u8),
@@ -205,21 +211,11 @@ impl Move {
             dst,
             castle: false,
             castle_long: false,
-            en_passant: if dst.3 != src.3 || !src_piece.is_pawn() || !dst_piece.is_blank() {
-                None
-            } else {
-                Some((
-                    dst.2,
-                    if src_piece.is_blank() {
-                        dst.3 - 1
-                    } else {
-                        dst.3 + 1
-                    },
-                ))
-            },
+            en_passant: en_passant_square(src, dst, src_piece, dst_piece),
             src_piece,
             dst_piece,
             noop: false,
+            promotion: default_promotion(dst, src_piece, game.height),
         })
     }
 
@@ -238,21 +234,11 @@ impl Move {
             dst,
             castle: false,
             castle_long: false,
-            en_passant: if dst.3 != src.3 || !src_piece.is_pawn() || !dst_piece.is_blank() {
-                None
-            } else {
-                Some((
-                    dst.2,
-                    if src_piece.is_blank() {
-                        dst.3 - 1
-                    } else {
-                        dst.3 + 1
-                    },
-                ))
-            },
+            en_passant: en_passant_square(src, dst, src_piece, dst_piece),
             src_piece,
             dst_piece,
             noop: false,
+            promotion: default_promotion(dst, src_piece, game.height),
         })
     }
 
@@ -273,6 +259,7 @@ impl Move {
             src_piece,
             dst_piece: if white { Piece::RookW } else { Piece::RookB },
             noop: false,
+            promotion: None,
         })
     }
 
@@ -287,6 +274,7 @@ impl Move {
             src_piece: Piece::Blank,
             dst_piece: Piece::Blank,
             noop: true,
+            promotion: None,
         }
     }
 
@@ -320,8 +308,8 @@ impl Move {
             new_board.set(self.dst.2, self.dst.3, Piece::Blank).unwrap();
 
             new_board.set(
-                self.src.2,
                 if self.castle_long { 2 } else { game.width - 2 },
+                self.src.3,
                 if new_board.active_player() {
                     Piece::KingB
                 } else {
@@ -329,14 +317,22 @@ impl Move {
                 },
             ).unwrap();
             new_board.set(
-                self.dst.2,
                 if self.castle_long { 3 } else { game.width - 3 },
+                self.dst.3,
                 if new_board.active_player() {
                     Piece::RookB
                 } else {
                     Piece::RookW
                 },
             ).unwrap();
+            let king = Some((if self.castle_long { 2 } else { game.width - 2 }, self.src.3));
+            if self.src_piece.is_white() {
+                new_board.king_w = king;
+                new_board.castle_w = (false, false);
+            } else {
+                new_board.king_b = king;
+                new_board.castle_b = (false, false);
+            }
             Some((info.clone(), vec![new_board]))
         } else if self.en_passant.is_some() {
             new_board.t += 1;
@@ -350,27 +346,11 @@ impl Move {
                 new_board.t += 1;
                 new_board.set(self.src.2, self.src.3, Piece::Blank).unwrap();
                 new_board.set(self.dst.2, self.dst.3, self.src_piece).unwrap();
+                self.update_castling(&mut new_board, true, true);
 
-                let info = info.clone();
+                self.promote(&mut new_board);
 
-                if self.src_piece.is_pawn()
-                    && self.dst.3
-                        == if self.src_piece.is_white() {
-                            new_board.height - 1
-                        } else {
-                            0
-                        }
-                {
-                    new_board.set(
-                        self.dst.2,
-                        self.dst.3,
-                        if self.src_piece.is_white() {
-                            Piece::QueenW
-                        } else {
-                            Piece::QueenB
-                        },
-                    ).unwrap();
-                }
+                let info = info.clone();
 
                 // Impossible!
                 // for b in already_generated {
@@ -411,30 +391,102 @@ impl Move {
 
                 new_src_board.t += 1;
                 new_dst_board.t += 1;
-                // TODO: timeline reactivation
-                if new_dst_board.t < new_info.present && new_dst_board.is_active(&new_info) {
+                // The present moves back to the new board or to the last board of a timeline that it reactivated
+                if new_dst_board.t < new_info.present && new_info.is_timeline_active(new_dst_board.l) {
                     new_info.present = new_dst_board.t;
                 }
-
-                if if self.src_piece.is_white() {
-                    -info.min_timeline > info.max_timeline
-                } else {
-                    -info.min_timeline < info.max_timeline
-                } {
-                    new_info.present = find_present(game, virtual_boards, info);
-                }
+                new_info.present = new_info.present.min(find_present(game, virtual_boards, &new_info));
 
                 new_src_board.set(self.src.2, self.src.3, Piece::Blank).unwrap();
                 new_dst_board.set(self.dst.2, self.dst.3, self.src_piece).unwrap();
+                self.update_castling(&mut new_src_board, true, false);
+                self.update_castling(&mut new_dst_board, false, true);
+                self.promote(&mut new_dst_board);
 
                 Some((new_info, vec![new_src_board, new_dst_board]))
             }
         }
     }
+
+    /// Replaces the moved pawn or brawn on `board` with its promotion piece, if any
+    fn promote(&self, board: &mut Board) {
+        if let Some(piece) = self.promotion {
+            board.set(self.dst.2, self.dst.3, piece).unwrap();
+        }
+    }
+
+    /**
+        Updates the king positions and castling rights of a board created by this (non-castling) move;
+        `is_src` and `is_dst` tell whether the piece left and/or arrived on that board.
+    **/
+    fn update_castling(&self, board: &mut Board, is_src: bool, is_dst: bool) {
+        if is_src {
+            if self.src_piece == Piece::KingW {
+                board.castle_w = (false, false);
+                board.king_w = if is_dst { Some((self.dst.2, self.dst.3)) } else { None };
+            } else if self.src_piece == Piece::KingB {
+                board.castle_b = (false, false);
+                board.king_b = if is_dst { Some((self.dst.2, self.dst.3)) } else { None };
+            } else if self.src_piece == Piece::RookW {
+                clear_castling_side(&mut board.castle_w, board.king_w, (self.src.2, self.src.3));
+            } else if self.src_piece == Piece::RookB {
+                clear_castling_side(&mut board.castle_b, board.king_b, (self.src.2, self.src.3));
+            }
+        }
+        if is_dst {
+            if self.dst_piece == Piece::RookW {
+                clear_castling_side(&mut board.castle_w, board.king_w, (self.dst.2, self.dst.3));
+            } else if self.dst_piece == Piece::RookB {
+                clear_castling_side(&mut board.castle_b, board.king_b, (self.dst.2, self.dst.3));
+            } else if self.dst_piece == Piece::KingW {
+                board.castle_w = (false, false);
+                board.king_w = None;
+            } else if self.dst_piece == Piece::KingB {
+                board.castle_b = (false, false);
+                board.king_b = None;
+            }
+        }
+    }
+}
+
+/// Removes the castling right corresponding to a rook at `rook` leaving or being taken; `castle` is `(long, short)`
+fn clear_castling_side(castle: &mut (bool, bool), king: Option<(u8, u8)>, rook: (u8, u8)) {
+    if let Some((king_x, king_y)) = king {
+        if rook.1 == king_y {
+            if rook.0 < king_x {
+                castle.0 = false;
+            } else {
+                castle.1 = false;
+            }
+        }
+    }
 }
 
 /// Returns the set of moves that can be made from `board`; does not check for the legality of said move (ie. if it puts the player in check)
 pub fn probable_moves(game: &Game, board: &Board, virtual_boards: &Vec<&Board>) -> Vec<Move> {
+    let mut res = piece_moves(game, board, virtual_boards);
+
+    castling_moves(game, board, virtual_boards, &mut res);
+
+    res
+}
+
+/// Returns the set of moves that the pieces of `board` can make, castling excluded; castling can't capture anything, so this is enough to look for checks
+pub fn piece_moves(game: &Game, board: &Board, virtual_boards: &Vec<&Board>) -> Vec<Move> {
+    let fast_path = if bitboard::fits(board.width, board.height) {
+        Some(neighbour_boards(game, board, virtual_boards))
+    } else {
+        None
+    };
+    board_moves(game, board, virtual_boards, fast_path)
+}
+
+/// Same as `piece_moves`, without the bitboard-based fast path; meant to check and benchmark the latter
+pub fn piece_moves_generic(game: &Game, board: &Board, virtual_boards: &Vec<&Board>) -> Vec<Move> {
+    board_moves(game, board, virtual_boards, None)
+}
+
+fn board_moves(game: &Game, board: &Board, virtual_boards: &Vec<&Board>, fast_path: Option<u32>) -> Vec<Move> {
     let mut res: Vec<Move> = Vec::new();
 
     for y in 0..board.height {
@@ -445,108 +497,95 @@ pub fn probable_moves(game: &Game, board: &Board, virtual_boards: &Vec<&Board>)
                 } else {
                     piece.is_black()
                 } {
-                    probable_moves_for(game, board, virtual_boards, &mut res, piece, x, y).unwrap();
+                    moves_for(game, board, virtual_boards, &mut res, piece, x, y, fast_path).unwrap();
                 }
             }
         }
     }
 
-    if board.active_player() && board.width > 5 {
-        if board.castle_w.0 {
-            // TODO: check the b and c file
-            let king_w = board.king_w.unwrap();
-            let (mut x, y) = king_w;
-            if x != 0 {
-                x -= 1;
-                while let Some(piece) = board.get(x, y) {
-                    if let Piece::RookW = piece {
-                        res.push(
-                            Move::castle(true, (board.l, board.t, king_w.0, king_w.1), (x, y), true)
-                                .unwrap(),
-                        );
-                        break;
-                    } else if let Piece::Blank = piece {
-                        if x == 0 {
-                            break;
-                        }
-                        x -= 1;
-                        continue;
-                    } else {
-                        break;
-                    }
-                }
-            }
+    res
+}
+
+/**
+    Appends the castling moves that can be made on `board` to `res`.
+    The squares between the king, the rook and their destinations must be empty and the king may not castle out of or through check.
+**/
+fn castling_moves(game: &Game, board: &Board, virtual_boards: &Vec<&Board>, res: &mut Vec<Move>) {
+    let white = board.active_player();
+    let (king, rights, king_piece, rook_piece) = if white {
+        (board.king_w, board.castle_w, Piece::KingW, Piece::RookW)
+    } else {
+        (board.king_b, board.castle_b, Piece::KingB, Piece::RookB)
+    };
+    let (king_x, y) = match king {
+        Some(king) => king,
+        None => return,
+    };
+    if board.width <= 5 || board.get(king_x, y) != Some(king_piece) {
+        return;
+    }
+
+    for &long in &[true, false] {
+        if !(if long { rights.0 } else { rights.1 }) {
+            continue;
         }
-        if board.castle_w.1 {
-            // TODO: check the f and g file
-            let king_w = board.king_w.unwrap();
-            let (mut x, y) = king_w;
-            x += 1;
-            while let Some(piece) = board.get(x, y) {
-                if let Piece::RookW = piece {
-                    res.push(
-                        Move::castle(false, (board.l, board.t, king_w.0, king_w.1), (x, y), true)
-                            .unwrap(),
-                    );
-                    break;
-                } else if let Piece::Blank = piece {
-                    x += 1;
-                    continue;
-                } else {
-                    break;
-                }
+
+        // The rook is the first piece found next to the king
+        let mut rook_x = king_x as isize;
+        loop {
+            rook_x += if long { -1 } else { 1 };
+            match board.get(rook_x as u8, y) {
+                Some(Piece::Blank) if rook_x > 0 => continue,
+                _ => break,
             }
         }
-    }
-    if !board.active_player() && board.width > 5 {
-        if board.castle_b.0 {
-            // TODO: check the b and c file
-            let king_b = board.king_b.unwrap();
-            let (mut x, y) = king_b;
-            if x != 0 {
-                x -= 1;
-                while let Some(piece) = board.get(x, y) {
-                    if let Piece::RookB = piece {
-                        res.push(
-                            Move::castle(true, (board.l, board.t, king_b.0, king_b.1), (x, y), false)
-                                .unwrap(),
-                        );
-                        break;
-                    } else if let Piece::Blank = piece {
-                        if x == 0 {
-                            break;
-                        }
-                        x -= 1;
-                        continue;
-                    } else {
-                        break;
-                    }
-                }
-            }
+        if rook_x < 0 || board.get(rook_x as u8, y) != Some(rook_piece) {
+            continue;
         }
-        if board.castle_b.1 {
-            // TODO: check the f and g file
-            let king_b = board.king_b.unwrap();
-            let (mut x, y) = king_b;
-            x += 1;
-            while let Some(piece) = board.get(x, y) {
-                if let Piece::RookB = piece {
-                    res.push(
-                        Move::castle(false, (board.l, board.t, king_b.0, king_b.1), (x, y), false)
-                            .unwrap(),
-                    );
-                    break;
-                } else if let Piece::Blank = piece {
-                    x += 1;
-                    continue;
-                } else {
-                    break;
-                }
-            }
+        let rook_x = rook_x as u8;
+
+        let king_dst = if long { 2 } else { board.width - 2 };
+        let rook_dst = if long { 3 } else { board.width - 3 };
+        let min = king_x.min(rook_x).min(king_dst).min(rook_dst);
+        let max = king_x.max(rook_x).max(king_dst).max(rook_dst);
+        if (min..=max).any(|x| x != king_x && x != rook_x && board.get(x, y) != Some(Piece::Blank)) {
+            continue;
         }
+
+        let (from, to) = (king_x.min(king_dst), king_x.max(king_dst));
+        if (from..=to).any(|x| is_attacked(game, board, virtual_boards, (king_x, y), (x, y))) {
+            continue;
+        }
+
+        res.push(Move::castle(long, (board.l, board.t, king_x, y), (rook_x, y), white).unwrap());
     }
+}
 
-    res
+/// Returns whether or not the opponent could capture the king of `board` (at `king`) if it stood on `square` instead, once the active player passed
+fn is_attacked(
+    game: &Game,
+    board: &Board,
+    virtual_boards: &Vec<&Board>,
+    king: (u8, u8),
+    square: (u8, u8),
+) -> bool {
+    let mut passed = board.clone();
+    let king_piece = passed.get_unsafe(king.0, king.1);
+    passed.t += 1;
+    passed.set_unsafe(king.0, king.1, Piece::Blank);
+    passed.set_unsafe(square.0, square.1, king_piece);
+
+    let mut merged_vboards = virtual_boards.clone();
+    merged_vboards.push(&passed);
+    let info = GameInfo {
+        active_player: board.active_player(),
+        ..game.info
+    };
+
+    get_opponent_boards(game, &merged_vboards, &info)
+        .into_iter()
+        .flat_map(|b| piece_moves(game, b, &merged_vboards))
+        .any(|m| m.dst == (passed.l, passed.t, square.0, square.1))
 }
 
 /// Returns whether or not a moveset is legal (ie. if it doesn't put the player in check).
@@ -560,15 +599,12 @@ where
     U: Iterator<Item = &'a Board>,
 {
     let opponent = !info.active_player;
+    let mut attackers: Vec<&Board> = Vec::new();
 
     for board in boards {
         if is_last(game, virtual_boards, board) {
             if board.active_player() == opponent {
-                for m in probable_moves(game, board, virtual_boards) {
-                    if m.dst_piece == (if opponent { Piece::KingB } else { Piece::KingW }) {
-                        return false;
-                    }
-                }
+                attackers.push(board);
             } else {
                 if board.is_active(info) {
                     return false;
@@ -577,7 +613,207 @@ where
         }
     }
 
-    true
+    !is_royal_attacked(game, virtual_boards, &attackers)
+}
+
+/**
+    Returns whether or not a piece of any of the `attackers` could take a royal piece, each board's active player being the attacker.
+
+    This gives the same result as looking for a royal `dst_piece` among the `piece_moves` of the `attackers`, but the lookup starts from the royal pieces:
+    only the directions leading from a royal piece to one of the `attackers` are followed and the lookup stops on the first attacking piece found.
+**/
+pub fn is_royal_attacked(game: &Game, virtual_boards: &[&Board], attackers: &[&Board]) -> bool {
+    attackers.iter().any(|attacker| royal_attacked_by(game, virtual_boards, attacker))
+}
+
+/**
+    Looks for a royal piece that a piece of `attacker` can take, on `attacker` or on the boards that a move from `attacker` can reach.
+    Those boards are looked up by their coordinates: the ones in line with `attacker` (on its timeline, on its turn or diagonally)
+    and the ones within a knight's jump.
+**/
+fn royal_attacked_by(game: &Game, virtual_boards: &[&Board], attacker: &Board) -> bool {
+    let white = !attacker.active_player();
+    let attacks = |board: &Board| {
+        let dl = (attacker.l - board.l) as isize;
+        let dt = (attacker.t - board.t) / 2;
+        let aligned = dl == 0 || dt == 0 || dl.abs() == dt.abs();
+        royal_squares(board, white).into_iter().any(|(x, y)| {
+            let royal = (board.l, board.t, x, y);
+            aligned && slider_attack(game, virtual_boards, attacker, royal, dl, dt)
+                || knight_attack(attacker, royal, dl, dt)
+                || pawn_attack(attacker, royal, dl, dt)
+        })
+    };
+
+    if attacks(attacker) {
+        return true;
+    }
+
+    let (min_timeline, max_timeline) = virtual_boards
+        .iter()
+        .fold((game.info.min_timeline, game.info.max_timeline), |(min, max), b| (min.min(b.l), max.max(b.l)));
+    for l in min_timeline..=max_timeline {
+        // Moves keep the parity of `t`; the displacement is measured in timelines and turns
+        let dl = (attacker.l - l).abs() as isize;
+        if dl == 0 {
+            // Every board of `attacker`'s timeline is in line with it
+            for step in [-2, 2] {
+                let mut t = attacker.t + step;
+                while let Some(board) = get_board(game, virtual_boards, (l, t)) {
+                    if attacks(board) {
+                        return true;
+                    }
+                    t += step;
+                }
+            }
+        } else {
+            let mut dts = vec![0, -dl, dl];
+            if dl <= 2 {
+                dts.extend(-2..=2);
+                dts.sort_unstable();
+                dts.dedup();
+            }
+            for dt in dts {
+                if let Some(board) = get_board(game, virtual_boards, (l, attacker.t - 2 * dt)) {
+                    if attacks(board) {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+
+    false
+}
+
+/// Returns the squares of the royal pieces of `white` on `board`
+fn royal_squares(board: &Board, white: bool) -> Vec<(u8, u8)> {
+    if bitboard::fits(board.width, board.height) {
+        let occupancy = board.occupancy();
+        bitboard::squares(occupancy.royal & occupancy.own(white)).collect()
+    } else {
+        (0..board.height)
+            .flat_map(|y| (0..board.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| {
+                let piece = board.get_unsafe(x, y);
+                piece.is_royal() && piece.is_own_piece(white)
+            })
+            .collect()
+    }
+}
+
+/// Returns whether or not a piece of `attacker`, `(dl, dt)` away from the `royal` square, can take it by sliding or by taking a king's step
+fn slider_attack(
+    game: &Game,
+    virtual_boards: &[&Board],
+    attacker: &Board,
+    royal: (i32, isize, u8, u8),
+    dl: isize,
+    dt: isize,
+) -> bool {
+    // Number of steps needed to reach `attacker`, unbounded if the royal piece stands on it
+    let distance = if dl == 0 && dt == 0 { isize::MAX } else { dl.abs().max(dt.abs()) };
+    for n in 1..=4 {
+        for permutation in &PERMUTATIONS[n] {
+            if permutation.0 != dl.signum() || permutation.1 != dt.signum() {
+                continue;
+            }
+            let mut length: isize = 1;
+            while length <= distance {
+                let x = royal.2 as isize + permutation.2 * length;
+                let y = royal.3 as isize + permutation.3 * length;
+                if x < 0 || x >= game.width as isize || y < 0 || y >= game.height as isize {
+                    break;
+                }
+                let pos = (royal.0 + (permutation.0 * length) as i32, royal.1 + permutation.1 * length * 2, x as u8, y as u8);
+                match get_with_board(game, attacker, virtual_boards, pos) {
+                    Some(Piece::Blank) => length += 1,
+                    Some(piece) => {
+                        if pos.0 == attacker.l
+                            && pos.1 == attacker.t
+                            && piece.is_own_piece(attacker.active_player())
+                            && attacks_along(piece, n, length)
+                        {
+                            return true;
+                        }
+                        break;
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Returns whether or not a knight of `attacker`, `(dl, dt)` away from the `royal` square, can take it
+fn knight_attack(attacker: &Board, royal: (i32, isize, u8, u8), dl: isize, dt: isize) -> bool {
+    PERMUTATIONS[0]
+        .iter()
+        .filter(|permutation| permutation.0 == dl && permutation.1 == dt)
+        .any(|permutation| {
+            let x = royal.2 as isize + permutation.2;
+            let y = royal.3 as isize + permutation.3;
+            x >= 0
+                && x < attacker.width as isize
+                && y >= 0
+                && y < attacker.height as isize
+                && attacker.get_unsafe(x as u8, y as u8).is_knight()
+                && attacker.get_unsafe(x as u8, y as u8).is_own_piece(attacker.active_player())
+        })
+}
+
+/// Returns whether or not a pawn or brawn of `attacker`, `(dl, dt)` away from the `royal` square, can take it (see `probable_moves_for`)
+fn pawn_attack(attacker: &Board, royal: (i32, isize, u8, u8), dl: isize, dt: isize) -> bool {
+    let white = attacker.active_player();
+    let dy: isize = if white { 1 } else { -1 };
+    let forward_l: isize = if white { -1 } else { 1 };
+    // The pawn moves by `(cl, ct)` from `attacker` to the royal piece's board
+    let (cl, ct) = (-dl, -dt);
+    if (cl != 0 && cl != forward_l) || ct.abs() > 1 {
+        return false;
+    }
+
+    for &cy in &[0, dy] {
+        if cl == 0 && cy == 0 {
+            continue;
+        }
+        for cx in -1isize..=1 {
+            let n_axes = (cl != 0) as u8 + (cy != 0) as u8 + (ct != 0) as u8 + (cx != 0) as u8;
+            let x = royal.2 as isize - cx;
+            let y = royal.3 as isize - cy;
+            if n_axes < 2 || x < 0 || x >= attacker.width as isize || y < 0 || y >= attacker.height as isize {
+                continue;
+            }
+            let piece = attacker.get_unsafe(x as u8, y as u8);
+            if !piece.is_own_piece(white) {
+                continue;
+            }
+            let takes = if piece.is_brawn() {
+                true
+            } else {
+                piece.is_pawn() && n_axes == 2 && (cy != 0 && cx != 0 || cl != 0 && ct != 0)
+            };
+            if takes {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Returns whether or not `piece` can move `length` steps along the `n`-th n-gonal (see `probable_moves_for`)
+fn attacks_along(piece: Piece, n: usize, length: isize) -> bool {
+    if length == 1 && (piece.is_king() || piece.is_common_king()) {
+        return true;
+    }
+    match n {
+        1 => piece.is_rook() || piece.is_princess() || piece.is_queen() || piece.is_royal_queen(),
+        2 => piece.is_bishop() || piece.is_princess() || piece.is_queen() || piece.is_royal_queen(),
+        3 => piece.is_unicorn() || piece.is_queen() || piece.is_royal_queen(),
+        4 => piece.is_dragon() || piece.is_queen() || piece.is_royal_queen(),
+        _ => false,
+    }
 }
 
 /// Returns whether or not every mandatory boards were played on (accepts time travel escapes)
@@ -630,14 +866,17 @@ pub fn get_own_boards<'a>(
     res
 }
 
-/// Returns a lazy iterator over the legal movesets that the active player can make
+/**
+    Returns a lazy iterator over the legal movesets that the active player can make.
+    Once it is exhausted, `ScoredMovesetIter::exhaustive` tells whether the limits cut the enumeration short.
+**/
 pub fn legal_movesets<'a>(
     game: &'a Game,
     info: &'a GameInfo,
     virtual_boards: &'a Vec<&'a Board>,
     max_moves_considered: usize,
     max_movesets_considered: usize,
-) -> impl Iterator<Item = (Vec<Move>, Vec<Board>, GameInfo, f32)> + 'a {
+) -> ScoredMovesetIter<'a> {
     let ranked_moves = get_own_boards(&game, &virtual_boards, &info)
         .into_iter()
         .map(|board| {
@@ -672,7 +911,7 @@ pub fn legal_movesets<'a>(
 /// Returns the `(l, t)` board within `game` or `virtual_boards`
 pub fn get_board<'a, 'b, 'd>(
     game: &'a Game,
-    virtual_boards: &'b Vec<&'b Board>,
+    virtual_boards: &'b [&'b Board],
     pos: (i32, isize),
 ) -> Option<&'d Board>
 where
@@ -702,7 +941,7 @@ fn get(
 fn get_with_board(
     game: &Game,
     board: &Board,
-    virtual_boards: &Vec<&Board>,
+    virtual_boards: &[&Board],
     pos: (i32, isize, u8, u8),
 ) -> Option<Piece> {
     if pos.0 == board.l && pos.1 == board.t {
@@ -738,13 +977,49 @@ pub fn probable_moves_for(
     piece: Piece,
     x: u8,
     y: u8,
+) -> Option<()> {
+    let fast_path = if bitboard::fits(board.width, board.height) {
+        Some(neighbour_boards(game, board, virtual_boards))
+    } else {
+        None
+    };
+    moves_for(game, board, virtual_boards, res, piece, x, y, fast_path)
+}
+
+/**
+    Appends the moves that `piece` can make to `res`.
+
+    `fast_path` holds, for boards that fit in bitboards, the boards around `board` that exist (see `neighbour_boards`).
+    The moves of knights, kings and sliders that stay on `board` are then found with its bitboards (see `bitboard_moves`),
+    and `n_gonal` only walks the directions whose first step lands on an existing board.
+    Without it, every direction is walked square by square.
+**/
+fn moves_for(
+    game: &Game,
+    board: &Board,
+    virtual_boards: &Vec<&Board>,
+    res: &mut Vec<Move>,
+    piece: Piece,
+    x: u8,
+    y: u8,
+    fast_path: Option<u32>,
 ) -> Option<()> {
     let src = (board.l, board.t, x, y);
     let active_player = board.active_player();
-    if piece.is_pawn() {
+    if piece.is_pawn() || piece.is_brawn() {
+        let first_move = res.len();
         let dy: isize = if piece.is_white() { 1 } else { -1 };
+        // Pawns move towards the opponent's side of the board and towards the opponent's timelines
+        let dl: i32 = if piece.is_white() { -1 } else { 1 };
+        let unmoved = if piece.is_white() {
+            y <= 1
+        } else {
+            y >= game.height - 2
+        };
         let y1 = ((y as isize) + dy) as u8;
         let y2 = ((y as isize) + 2 * dy) as u8;
+
+        // Forward, on the board
         if board.get(x, y1)? == Piece::Blank {
             res.push(Move::new_with_board(
                 src,
@@ -753,13 +1028,7 @@ pub fn probable_moves_for(
                 board,
                 virtual_boards,
             )?);
-            if if piece.is_white() {
-                y <= 1
-            } else {
-                y >= game.height - 2
-            } && board.get(x, y2)? == Piece::Blank
-            {
-                // TODO: handle 1-pawn better
+            if unmoved && board.get(x, y2)? == Piece::Blank {
                 res.push(Move::new_with_board(
                     src,
                     (board.l, board.t, x, y2),
@@ -769,73 +1038,82 @@ pub fn probable_moves_for(
                 )?);
             }
         }
-        // Try to take on x + 1
-        if x < game.width - 1
-            && (may_en_passant(game, board, virtual_boards, x + 1, y1)
-                || board.get(x + 1, y1)?
-                    .is_opponent_piece(active_player))
-        {
-            res.push(Move::new_with_board(
-                src,
-                (board.l, board.t, x + 1, y1),
-                game,
-                board,
-                virtual_boards,
-            )?);
-        }
-        // Try to take on x - 1
-        if x > 0
-            && (may_en_passant(game, board, virtual_boards, x - 1, y1)
-                || board.get(x - 1, y1)?
-                    .is_opponent_piece(active_player))
-        {
-            res.push(Move::new_with_board(
-                src,
-                (board.l, board.t, x - 1, y1),
-                game,
-                board,
-                virtual_boards,
-            )?);
+
+        // Forward, along the timelines
+        let l1 = board.l + dl;
+        let l2 = board.l + 2 * dl;
+        if get_with_board(game, board, virtual_boards, (l1, board.t, x, y)) == Some(Piece::Blank) {
+            res.push(Move::new_with_board(src, (l1, board.t, x, y), game, board, virtual_boards)?);
+            if unmoved && get_with_board(game, board, virtual_boards, (l2, board.t, x, y)) == Some(Piece::Blank) {
+                res.push(Move::new_with_board(src, (l2, board.t, x, y), game, board, virtual_boards)?);
+            }
         }
-    } else if piece.is_king() {
-        for dl in -1isize..=1isize {
-            for dt in -1isize..=1isize {
-                for dy in -1isize..=1isize {
-                    for dx in -1isize..=1isize {
-                        if dx == 0 && dy == 0 && dl == 0 && dt == 0
-                            || x == 0 && dx < 0
-                            || x == game.width - 1 && dx > 0
-                            || y == 0 && dy < 0
-                            || y == game.height - 1 && dy > 0
-                            || board.t < 2 && dt < 0
-                        {
-                            continue;
-                        }
-                        let l1 = if dl == -1 {
-                            board.l - 1
-                        } else if dl == 1 {
-                            board.l + 1
+
+        /* Captures: pawns capture one step forward (along `y` or `l`) and one step sideways (along `x` or `t` respectively);
+           brawns may also combine both forward axes and take one step along any of the other axes. */
+        for &cl in &[0, dl] {
+            for &cy in &[0, dy] {
+                if cl == 0 && cy == 0 {
+                    continue;
+                }
+                for ct in -1isize..=1 {
+                    for cx in -1isize..=1 {
+                        let n_axes = (cl != 0) as u8 + (cy != 0) as u8 + (ct != 0) as u8 + (cx != 0) as u8;
+                        let allowed = if piece.is_brawn() {
+                            n_axes >= 2
                         } else {
-                            board.l
+                            n_axes == 2 && (cy != 0 && cx != 0 || cl != 0 && ct != 0)
                         };
-                        let t1 = board.t + 2 * dt;
-                        let x1 = ((x as isize) + dx) as u8;
-                        let y1 = ((y as isize) + dy) as u8;
-                        if let Some(true) = get_with_board(game, board, virtual_boards, (l1, t1, x1, y1))
-                            .map(|p| p.is_takable_piece(active_player))
+                        let x1 = x as isize + cx;
+                        let y1 = y as isize + cy;
+                        if !allowed || x1 < 0 || x1 >= game.width as isize || y1 < 0 || y1 >= game.height as isize {
+                            continue;
+                        }
+                        let dst = (board.l + cl, board.t + 2 * ct, x1 as u8, y1 as u8);
+                        let en_passant = cl == 0 && ct == 0 && may_en_passant(game, board, virtual_boards, dst.2, dst.3);
+                        if en_passant
+                            || get_with_board(game, board, virtual_boards, dst)
+                                .map(|p| p.is_opponent_piece(active_player))
+                                .unwrap_or(false)
                         {
-                            res.push(Move::new_with_board(
-                                src,
-                                (l1, t1, x1, y1),
-                                game,
-                                board,
-                                virtual_boards,
-                            )?);
+                            res.push(Move::new_with_board(src, dst, game, board, virtual_boards)?);
                         }
                     }
                 }
             }
         }
+
+        // Moves reaching the last rank are repeated for every promotion target
+        if res[first_move..].iter().any(|mv| mv.promotion.is_some()) {
+            let targets = promotion_pieces(game, piece.is_white());
+            for mv in res.split_off(first_move) {
+                if mv.promotion.is_some() {
+                    for target in &targets {
+                        res.push(Move {
+                            promotion: Some(*target),
+                            ..mv
+                        });
+                    }
+                } else {
+                    res.push(mv);
+                }
+            }
+        }
+    } else if piece.is_king() || piece.is_common_king() {
+        // One step along any of the n-gonals
+        for n in 1..=4 {
+            n_gonal_range(
+                game,
+                board,
+                virtual_boards,
+                res,
+                (board.l, board.t, x, y),
+                n,
+                1,
+                active_player,
+                fast_path,
+            )?;
+        }
     } else if piece.is_knight() {
         n_gonal(
             game,
@@ -845,6 +1123,7 @@ pub fn probable_moves_for(
             (board.l, board.t, x, y),
             0,
             active_player,
+            fast_path,
         )?;
     } else if piece.is_rook() {
         n_gonal(
@@ -855,6 +1134,7 @@ pub fn probable_moves_for(
             (board.l, board.t, x, y),
             1,
             active_player,
+            fast_path,
         )?;
     } else if piece.is_bishop() {
         n_gonal(
@@ -865,6 +1145,7 @@ pub fn probable_moves_for(
             (board.l, board.t, x, y),
             2,
             active_player,
+            fast_path,
         )?;
     } else if piece.is_unicorn() {
         n_gonal(
@@ -875,6 +1156,7 @@ pub fn probable_moves_for(
             (board.l, board.t, x, y),
             3,
             active_player,
+            fast_path,
         )?;
     } else if piece.is_dragon() {
         n_gonal(
@@ -885,8 +1167,9 @@ pub fn probable_moves_for(
             (board.l, board.t, x, y),
             4,
             active_player,
+            fast_path,
         )?;
-    } else if piece.is_queen() {
+    } else if piece.is_queen() || piece.is_royal_queen() {
         n_gonal(
             game,
             board,
@@ -895,6 +1178,7 @@ pub fn probable_moves_for(
             (board.l, board.t, x, y),
             1,
             active_player,
+            fast_path,
         )?;
         n_gonal(
             game,
@@ -904,6 +1188,7 @@ pub fn probable_moves_for(
             (board.l, board.t, x, y),
             2,
             active_player,
+            fast_path,
         )?;
         n_gonal(
             game,
@@ -913,6 +1198,7 @@ pub fn probable_moves_for(
             (board.l, board.t, x, y),
             3,
             active_player,
+            fast_path,
         )?;
         n_gonal(
             game,
@@ -922,6 +1208,7 @@ pub fn probable_moves_for(
             (board.l, board.t, x, y),
             4,
             active_player,
+            fast_path,
         )?;
     } else if piece.is_princess() {
         n_gonal(
@@ -932,6 +1219,7 @@ pub fn probable_moves_for(
             (board.l, board.t, x, y),
             1,
             active_player,
+            fast_path,
         )?;
         n_gonal(
             game,
@@ -941,11 +1229,106 @@ pub fn probable_moves_for(
             (board.l, board.t, x, y),
             2,
             active_player,
+            fast_path,
         )?;
     }
+    if fast_path.is_some() {
+        bitboard_moves(game, board, virtual_boards, res, piece, x, y)?;
+    }
+    Some(())
+}
+
+/// Appends the moves of `piece` that stay on `board` to `res`, if it is a knight, a king or a slider; `board` must fit in bitboards
+fn bitboard_moves(
+    game: &Game,
+    board: &Board,
+    virtual_boards: &Vec<&Board>,
+    res: &mut Vec<Move>,
+    piece: Piece,
+    x: u8,
+    y: u8,
+) -> Option<()> {
+    let occupancy = board.occupancy();
+    let occupied = occupancy.all();
+    let mut targets = if piece.is_knight() {
+        bitboard::knight_attacks(x, y)
+    } else if piece.is_king() || piece.is_common_king() {
+        bitboard::king_attacks(x, y)
+    } else {
+        0
+    };
+    if piece.is_rook() || piece.is_queen() || piece.is_royal_queen() || piece.is_princess() {
+        targets |= bitboard::rook_attacks(x, y, occupied);
+    }
+    if piece.is_bishop() || piece.is_queen() || piece.is_royal_queen() || piece.is_princess() {
+        targets |= bitboard::bishop_attacks(x, y, occupied);
+    }
+    targets &= bitboard::valid_squares(board.width, board.height) & !occupancy.own(board.active_player());
+
+    let src = (board.l, board.t, x, y);
+    for (x1, y1) in bitboard::squares(targets) {
+        res.push(Move::new_with_board(src, (board.l, board.t, x1, y1), game, board, virtual_boards)?);
+    }
     Some(())
 }
 
+/// Returns the queen that a pawn or brawn moving to `dst` promotes to by default, if `dst` lies on its last rank
+fn default_promotion(dst: (i32, isize, u8, u8), src_piece: Piece, height: u8) -> Option<Piece> {
+    if !src_piece.is_pawn() && !src_piece.is_brawn() {
+        None
+    } else if src_piece.is_white() && dst.3 == height - 1 {
+        Some(Piece::QueenW)
+    } else if src_piece.is_black() && dst.3 == 0 {
+        Some(Piece::QueenB)
+    } else {
+        None
+    }
+}
+
+/**
+    Returns the pieces that a pawn or brawn of the given color can promote to: queens, knights, rooks and bishops,
+    as well as princesses, unicorns and dragons in the variants that start with any of them.
+**/
+pub fn promotion_pieces(game: &Game, white: bool) -> Vec<Piece> {
+    let mut res = if white {
+        vec![Piece::QueenW, Piece::KnightW, Piece::RookW, Piece::BishopW]
+    } else {
+        vec![Piece::QueenB, Piece::KnightB, Piece::RookB, Piece::BishopB]
+    };
+    let variant_pieces = if white {
+        [Piece::PrincessW, Piece::UnicornW, Piece::DragonW]
+    } else {
+        [Piece::PrincessB, Piece::UnicornB, Piece::DragonB]
+    };
+    let initial_pieces = game
+        .timelines
+        .values()
+        .filter_map(|tl| tl.states.first())
+        .flat_map(|b| b.pieces())
+        .map(|p| p.as_uppercase())
+        .collect::<std::collections::HashSet<_>>();
+    for piece in &variant_pieces {
+        if initial_pieces.contains(piece.as_uppercase()) {
+            res.push(*piece);
+        }
+    }
+    res
+}
+
+/// Returns the square of the pawn taken en passant by a move, if any: a pawn or brawn moving diagonally on its board onto an empty square
+fn en_passant_square(
+    src: (i32, isize, u8, u8),
+    dst: (i32, isize, u8, u8),
+    src_piece: Piece,
+    dst_piece: Piece,
+) -> Option<(u8, u8)> {
+    if (src_piece.is_pawn() || src_piece.is_brawn()) && dst_piece.is_blank() && src.0 == dst.0 && src.1 == dst.1 && src.2 != dst.2 {
+        Some((dst.2, src.3))
+    } else {
+        None
+    }
+}
+
 /// Returns if the `x, y` square in `board` can be taken as en-passant (`[ɑ̃ pasɑ̃]`)
 fn may_en_passant(
     game: &Game,
@@ -975,7 +1358,7 @@ fn may_en_passant(
     }
 }
 
-/// Generate the moves for n-gonals of pieces (knight's is the `0`-th n-gonal)
+/// Generate the moves for n-gonals of pieces (knight's is the `0`-th n-gonal); see `moves_for` for `fast_path`
 fn n_gonal(
     game: &Game,
     board: &Board,
@@ -984,8 +1367,31 @@ fn n_gonal(
     src: (i32, isize, u8, u8),
     n: usize,
     active_player: bool,
+    fast_path: Option<u32>,
+) -> Option<()> {
+    let max_length = if n == 0 { 1 } else { std::isize::MAX };
+    n_gonal_range(game, board, virtual_boards, res, src, n, max_length, active_player, fast_path)
+}
+
+/// Generate the moves for n-gonals of pieces, moving at most `max_length` steps
+fn n_gonal_range(
+    game: &Game,
+    board: &Board,
+    virtual_boards: &Vec<&Board>,
+    res: &mut Vec<Move>,
+    src: (i32, isize, u8, u8),
+    n: usize,
+    max_length: isize,
+    active_player: bool,
+    fast_path: Option<u32>,
 ) -> Option<()> {
     for permutation in &PERMUTATIONS[n] {
+        if let Some(neighbours) = fast_path {
+            let on_board = permutation.0 == 0 && permutation.1 == 0;
+            if on_board || neighbours & 1 << neighbour_index(permutation.0, permutation.1) == 0 {
+                continue;
+            }
+        }
         let mut length: isize = 1;
         loop {
             let l0 = (src.0 as isize + permutation.0 * length) as i32;
@@ -1007,7 +1413,7 @@ fn n_gonal(
             } else {
                 break;
             }
-            if n == 0 {
+            if length >= max_length {
                 break;
             }
             length += 1;
@@ -1016,41 +1422,51 @@ fn n_gonal(
     Some(())
 }
 
-/// Re-calculate the present
-pub fn find_present(game: &Game, virtual_boards: &Vec<&Board>, info: &GameInfo) -> isize {
-    let mut min = info.present;
-    game.timelines
-        .values()
-        .map(|tl| &tl.states[tl.states.len() - 1])
-        .filter(|b| is_last(game, virtual_boards, b) && b.is_active(info))
-        .for_each(|b| {
-            if b.t < min {
-                min = b.t;
+/**
+    Returns which of the `(board.l + dl, board.t + 2 * dt)` boards exist within `game` or `virtual_boards`, for `dl` and `dt` in `-2..=2`,
+    as a mask of `1 << neighbour_index(dl, dt)` bits; `board` itself is included.
+    As sliders stop on the first missing board, the directions whose first step leads to a missing board can be skipped altogether.
+**/
+fn neighbour_boards(game: &Game, board: &Board, virtual_boards: &Vec<&Board>) -> u32 {
+    let mut res = 1 << neighbour_index(0, 0);
+    for dl in -2..=2 {
+        for dt in -2..=2 {
+            if get_board(game, virtual_boards, (board.l + dl as i32, board.t + 2 * dt)).is_some() {
+                res |= 1 << neighbour_index(dl, dt);
             }
-        });
-    for b in virtual_boards {
-        if is_last(game, virtual_boards, b) && b.t < min && b.is_active(info) {
-            min = b.t;
         }
     }
+    res
+}
 
-    min
+#[inline]
+fn neighbour_index(dl: isize, dt: isize) -> u32 {
+    ((dl + 2) * 5 + dt + 2) as u32
+}
+
+/// Re-calculate the present: the time coordinate of the earliest last board among the active timelines (see `GameInfo::active_timelines`)
+pub fn find_present(game: &Game, virtual_boards: &Vec<&Board>, info: &GameInfo) -> isize {
+    game.timelines
+        .values()
+        .filter_map(|tl| tl.states.last())
+        .chain(virtual_boards.iter().map(|b| *b))
+        .filter(|b| info.is_timeline_active(b.l) && is_last(game, virtual_boards, b))
+        .map(|b| b.t)
+        .min()
+        .unwrap_or(info.present)
 }
 
 // TODO: fix this
 /// Returns whether or not making the move `mv` is optional (currently unused, might change it to if the move can be safely omitted)
 pub fn is_optional(info: &GameInfo, mv: &Move) -> bool {
-    if mv.src.1 > info.present
-        || mv.src.0 < -info.max_timeline - 1
-        || mv.src.0 > -info.min_timeline + 1
-    {
+    if mv.src.1 > info.present || !info.is_timeline_active(mv.src.0) {
         mv.src.0 == mv.dst.0 && mv.src.1 == mv.dst.1
     } else {
         false
     }
 }
 
-/// Returns whether or not the game is a draw; assumes that no move can be made
+/// Returns whether or not the game is a draw; assumes that no move can be made (see `status::game_status` to check that this holds)
 pub fn is_draw(game: &Game, virtual_boards: &Vec<&Board>, info: &GameInfo) -> bool {
     let opponent_boards = get_opponent_boards(game, virtual_boards, info).into_iter().filter(|b| b.is_active(info)).collect::<Vec<_>>();
     let own_boards = get_own_boards(game, virtual_boards, info)
@@ -1072,21 +1488,97 @@ pub fn is_draw(game: &Game, virtual_boards: &Vec<&Board>, info: &GameInfo) -> bo
 
     // TODO: merge mutated own_boards with virtual_boards
 
-    for b in opponent_boards.into_iter() {
-        for mv in probable_moves(game, b, &merged_vboards) {
-            if mv.dst_piece.is_king() {
-                return false;
-            }
-        }
+    let attackers = opponent_boards
+        .into_iter()
+        .chain(own_boards.iter())
+        .collect::<Vec<_>>();
+
+    !is_royal_attacked(game, &merged_vboards, &attackers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{notation, replay};
+
+    #[test]
+    fn brawns_take_en_passant() {
+        let mut game = notation::parse("[Board \"custom\"]\n[4k3/3p4/8/4W3/8/8/8/4K3:0:1:w]\n\n1. (0T1)Kf1 / (0T1)d5\n").unwrap().game;
+        let board = game.get_board(0, 2).unwrap();
+        let mv = probable_moves(&game, board, &vec![])
+            .into_iter()
+            .find(|mv| mv.src_piece == Piece::BrawnW && mv.dst == (0, 2, 3, 5))
+            .expect("The brawn can't take en passant");
+        assert_eq!(mv.en_passant, Some((3, 4)));
+
+        replay::play_moveset(&mut game, &[mv]).unwrap();
+        let board = game.get_board(0, 3).unwrap();
+        assert_eq!(board.get(3, 5), Some(Piece::BrawnW));
+        assert_eq!(board.get(3, 4), Some(Piece::Blank));
+    }
+
+    #[test]
+    fn legal_movesets_tell_whether_they_were_cut_short() {
+        let game = crate::variants::Variant::Standard.game();
+        let virtual_boards = vec![];
+
+        let mut movesets = legal_movesets(&game, &game.info, &virtual_boards, 0, 0);
+        assert_eq!(movesets.by_ref().count(), 20);
+        assert!(movesets.exhaustive());
+
+        let mut movesets = legal_movesets(&game, &game.info, &virtual_boards, 0, 5);
+        assert_eq!(movesets.by_ref().count(), 5);
+        assert!(!movesets.exhaustive());
     }
 
-    for b in own_boards.iter() {
-        for mv in probable_moves(game, &b, &merged_vboards) {
-            if mv.dst_piece.is_king() {
-                return false;
+    /**
+        Compares `is_royal_attacked` to a scan of the `piece_moves` of the last boards of `raw` and of the positions that its movesets lead to.
+        Each board is also checked as if it were passed on to its opponent, like `is_moveset_legal` does.
+        Returns the number of boards checked and the number of them that attack a royal piece.
+    **/
+    fn compare_royal_attacks(raw: &str) -> (usize, usize) {
+        fn compare(game: &Game, virtual_boards: &Vec<&Board>, res: &mut (usize, usize)) {
+            let last_boards = game
+                .timelines
+                .values()
+                .map(|tl| &tl.states[tl.states.len() - 1])
+                .chain(virtual_boards.iter().copied())
+                .filter(|b| is_last(game, virtual_boards, b))
+                .collect::<Vec<_>>();
+            for board in last_boards {
+                let mut passed = board.clone();
+                passed.t += 1;
+                let merged_vboards = virtual_boards.iter().copied().chain(std::iter::once(&passed)).collect::<Vec<_>>();
+                for (attacker, vboards) in [(board, virtual_boards), (&passed, &merged_vboards)] {
+                    let expected = piece_moves(game, attacker, vboards)
+                        .iter()
+                        .any(|mv| mv.dst_piece.is_royal() && mv.dst_piece.is_opponent_piece(attacker.active_player()));
+                    assert_eq!(is_royal_attacked(game, vboards, &[attacker]), expected, "{:?}\n{}", (attacker.l, attacker.t), attacker);
+                    res.0 += 1;
+                    res.1 += expected as usize;
+                }
             }
         }
+
+        let game = notation::parse(raw).unwrap().game;
+        let mut res = (0, 0);
+        let no_boards = vec![];
+        compare(&game, &no_boards, &mut res);
+        for (_moveset, boards, _info, _score) in legal_movesets(&game, &game.info, &no_boards, 0, 0) {
+            compare(&game, &boards.iter().collect(), &mut res);
+        }
+        res
     }
 
-    true
+    #[test]
+    fn royal_attacks_match_piece_moves() {
+        for raw in [
+            "[Board \"Standard\"]\n\n1. (0T1)e3 / (0T1)f6\n",
+            "[Board \"Standard\"]\n\n1. (0T1)Ng1f3 / (0T1)Ng8f6\n2. (0T2)Nf3>>(0T1)f5 / (1T1)Nb8c6\n3. (1T2)e3 / (0T2)e6 (1T2)e6\n",
+            "[Board \"Standard - Two Timelines\"]\n\n1. (-0T1)e3 (+0T1)e3 / (-0T1)f6 (+0T1)f6\n",
+        ] {
+            let (checked, attacked) = compare_royal_attacks(raw);
+            assert!(attacked > 0 && attacked < checked, "{}: {} boards attacked out of {}", raw, attacked, checked);
+        }
+    }
 }
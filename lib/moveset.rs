@@ -24,6 +24,8 @@ pub struct MovesetIter<'a> {
     pub max_movesets_considered: usize, // 0 for ∞
     /// The number of movesets that have been yielded already
     pub movesets_considered: usize,
+    /// Whether or not `max_moves_considered` or `max_movesets_considered` kept some movesets from being yielded
+    cut_short: bool,
 }
 
 impl<'a> Iterator for MovesetIter<'a> {
@@ -35,8 +37,11 @@ impl<'a> Iterator for MovesetIter<'a> {
         if self.max_movesets_considered > 0
             && self.movesets_considered > self.max_movesets_considered
             || self.max_moves_considered > 0 && self.moves_considered > self.max_moves_considered
-            || self.moves_considered > self.max_moves
         {
+            self.cut_short = true;
+            return None;
+        }
+        if self.moves_considered > self.max_moves {
             return None;
         }
 
@@ -50,6 +55,7 @@ impl<'a> Iterator for MovesetIter<'a> {
                         if self.max_moves_considered > 0
                             && self.moves_considered > self.max_moves_considered
                         {
+                            self.cut_short = true;
                             return None;
                         }
 
@@ -105,9 +111,18 @@ impl<'a> MovesetIter<'a> {
             max_movesets_considered: 0,
             max_moves_considered: 0,
             movesets_considered: 0,
+            cut_short: false,
         }
     }
 
+    /**
+    Returns whether or not every moveset was looked at, ie. that none were left out because of `max_moves_considered` or `max_movesets_considered`.
+    Only meaningful once the iterator returned `None`.
+    **/
+    pub fn exhaustive(&self) -> bool {
+        !self.cut_short
+    }
+
     /**
     Called by `next()` when the `permutation_stack` empties out.
     It increases `moves_considered` and generates the combinations made using the new, considered moves.
@@ -250,6 +265,7 @@ impl<'a> MovesetIter<'a> {
             if self.max_movesets_considered > 0
                 && self.permutation_stack.len() > self.max_movesets_considered
             {
+                self.cut_short = true;
                 break;
             }
 
@@ -269,22 +285,45 @@ impl<'a> MovesetIter<'a> {
     /**
     Lazily applies the `score_moveset` function to the movesets and filters out the illegal movesets
     **/
-    pub fn score(self) -> impl Iterator<Item = (Vec<Move>, Vec<Board>, GameInfo, f32)> + 'a {
-        // NOTE: I still don't quite understand why this doesn't fail to compile
-        let game = self.game;
-        let virtual_boards = self.virtual_boards;
-        let info = self.info;
+    pub fn score(self) -> ScoredMovesetIter<'a> {
+        ScoredMovesetIter { inner: self }
+    }
+}
 
-        self.map(move |ms| {
-            score_moveset(
-                &game,
-                &virtual_boards,
-                &info,
-                get_opponent_boards(game, virtual_boards, &info).into_iter(),
+/**
+    The iterator returned by `MovesetIter::score`: yields the legal movesets along with their boards, info and score.
+**/
+pub struct ScoredMovesetIter<'a> {
+    inner: MovesetIter<'a>,
+}
+
+impl<'a> Iterator for ScoredMovesetIter<'a> {
+    type Item = (Vec<Move>, Vec<Board>, GameInfo, f32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let ms = self.inner.next()?;
+            let game = self.inner.game;
+            let virtual_boards = self.inner.virtual_boards;
+            let info = &self.inner.info;
+            let scored = score_moveset(
+                game,
+                virtual_boards,
+                info,
+                get_opponent_boards(game, virtual_boards, info).into_iter(),
                 ms,
-            )
-        })
-        .filter(|x| x.is_some())
-        .map(|x| x.unwrap())
+            );
+            if scored.is_some() {
+                return scored;
+            }
+        }
     }
 }
+
+impl<'a> ScoredMovesetIter<'a> {
+    /// See `MovesetIter::exhaustive`
+    pub fn exhaustive(&self) -> bool {
+        self.inner.exhaustive()
+    }
+}
+
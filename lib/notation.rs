@@ -0,0 +1,850 @@
+/*
+    Reading and writing games in the 5D chess notation (5dpgn).
+
+    A record is made of headers (`[Key "Value"]`), optional custom boards (5DFEN, for instance `[8/8/3k4/8/8/4K3/8/8:0:1:w]`)
+    and a list of turns (`1. (0T1)e3 / (0T1)e6`).
+*/
+
+use crate::{game::*, moves::*, replay::{self, ReplayError}, variants::{self, Variant}};
+use std::fmt;
+
+/// A parsed game: its headers, its starting position and the movesets played from there on
+#[derive(Debug)]
+pub struct GameRecord {
+    pub headers: Vec<(String, String)>,
+    pub initial_boards: Vec<Board>,
+    pub width: u8,
+    pub height: u8,
+    pub even_initial_timelines: bool,
+    pub turns: Vec<Vec<Move>>,
+    /// The position reached after playing every turn
+    pub game: Game,
+}
+
+/// An error encountered while reading a record; `line` starts at 1 and is 0 if the error isn't tied to a line
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotationError {
+    pub line: usize,
+    pub kind: NotationErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotationErrorKind {
+    /// A header line could not be read
+    InvalidHeader(String),
+    /// The `Board` header names a variant that isn't known
+    UnknownVariant(String),
+    /// A custom board (5DFEN) could not be read
+    InvalidBoard(String),
+    /// A move could not be read
+    InvalidMove(String),
+    /// A move was read, but no such move can be made in the position
+    NoSuchMove(String),
+    /// A move was read, but several moves match it
+    AmbiguousMove(String),
+    /// The moves of a turn could not be played together
+    IllegalMoveset(ReplayError),
+    /// The promotion target isn't a piece that pawns can promote to in this variant
+    UnsupportedPromotion(String),
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl fmt::Display for NotationErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotationErrorKind::InvalidHeader(s) => write!(f, "invalid header: {}", s),
+            NotationErrorKind::UnknownVariant(s) => write!(f, "unknown variant: {}", s),
+            NotationErrorKind::InvalidBoard(s) => write!(f, "invalid board: {}", s),
+            NotationErrorKind::InvalidMove(s) => write!(f, "invalid move: {}", s),
+            NotationErrorKind::NoSuchMove(s) => write!(f, "no such move: {}", s),
+            NotationErrorKind::AmbiguousMove(s) => write!(f, "ambiguous move: {}", s),
+            NotationErrorKind::IllegalMoveset(err) => write!(f, "{}", err),
+            NotationErrorKind::UnsupportedPromotion(s) => write!(f, "unsupported promotion: {}", s),
+        }
+    }
+}
+
+impl NotationError {
+    fn new(line: usize, kind: NotationErrorKind) -> Self {
+        NotationError { line, kind }
+    }
+}
+
+/// A move, as it is written; coordinates are resolved against the game once the active player is known
+#[derive(Debug, Clone, PartialEq)]
+struct RawMove {
+    src_board: (i32, isize),
+    piece: Option<char>,
+    src_file: Option<u8>,
+    src_rank: Option<u8>,
+    dst_board: Option<(i32, isize)>,
+    dst: (u8, u8),
+    castle: Option<bool>,
+    promotion: Option<char>,
+}
+
+impl GameRecord {
+    /// Returns the value of the `key` header, if present (case-insensitive)
+    pub fn header<'a>(&'a self, key: &str) -> Option<&'a str> {
+        self.headers
+            .iter()
+            .find(|(k, _v)| k.eq_ignore_ascii_case(key))
+            .map(|(_k, v)| v.as_str())
+    }
+
+    /// Creates a new `Game` instance, holding the starting position of the record
+    pub fn initial_game(&self) -> Game {
+        variants::from_boards(&self.initial_boards, self.even_initial_timelines)
+    }
+
+    /// Creates a record with no turn, whose starting position is every board of `game` (written as custom boards)
+    pub fn from_game(game: Game) -> Self {
+        let mut timelines = game.timelines.values().collect::<Vec<_>>();
+        timelines.sort_by_key(|tl| tl.index);
+        let initial_boards = timelines
+            .into_iter()
+            .flat_map(|tl| tl.states.iter().cloned())
+            .collect::<Vec<_>>();
+
+        GameRecord {
+            headers: vec![(String::from("Board"), String::from("custom"))],
+            initial_boards,
+            width: game.width,
+            height: game.height,
+            even_initial_timelines: game.info.even_initial_timelines,
+            turns: vec![],
+            game,
+        }
+    }
+}
+
+/**
+    Reads a game written in 5dpgn and replays it.
+
+    ```
+    let record = notation::parse("[Board \"Standard\"]\n1. (0T1)e3 / (0T1)e6\n").unwrap();
+    println!("{}", record.game.info.present);
+    ```
+**/
+pub fn parse(raw: &str) -> Result<GameRecord, NotationError> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut fen_boards: Vec<(usize, String)> = Vec::new();
+    let mut movetext: Vec<(usize, &str)> = Vec::new();
+
+    for (n, line) in raw.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && movetext.is_empty() {
+            if !trimmed.ends_with(']') {
+                return Err(NotationError::new(n + 1, NotationErrorKind::InvalidHeader(trimmed.to_string())));
+            }
+            let inner = trimmed[1..trimmed.len() - 1].trim();
+            if let Some(quote) = inner.find('"') {
+                let key = inner[..quote].trim();
+                let value = inner[quote..].trim().trim_matches('"');
+                if key.is_empty() {
+                    return Err(NotationError::new(n + 1, NotationErrorKind::InvalidHeader(trimmed.to_string())));
+                }
+                headers.push((key.to_string(), value.to_string()));
+            } else {
+                fen_boards.push((n + 1, inner.to_string()));
+            }
+        } else if !trimmed.is_empty() {
+            movetext.push((n + 1, line));
+        }
+    }
+
+    let variant = headers
+        .iter()
+        .find(|(k, _v)| k.eq_ignore_ascii_case("board"))
+        .map(|(_k, v)| v.clone())
+        .unwrap_or_else(|| String::from(if fen_boards.is_empty() { "Standard" } else { "custom" }));
+
+    if !variant.eq_ignore_ascii_case("custom") {
+        match Variant::from_name(&variant) {
+            Some(variant) => {
+                fen_boards = variant.boards().iter().map(|b| (0, b.to_string())).collect();
+            }
+            None => return Err(NotationError::new(0, NotationErrorKind::UnknownVariant(variant))),
+        }
+    }
+
+    // The timeline indices of the boards can only be read once every board has been seen
+    let even_initial_timelines = fen_even_timelines(fen_boards.iter().map(|(_line, fen)| fen.as_str()));
+    let mut initial_boards: Vec<Board> = Vec::with_capacity(fen_boards.len());
+    for (line, fen) in &fen_boards {
+        let board = parse_fen(fen, even_initial_timelines)
+            .ok_or_else(|| NotationError::new(*line, NotationErrorKind::InvalidBoard(fen.clone())))?;
+        initial_boards.push(board);
+    }
+    let (width, height) = match initial_boards.first() {
+        Some(b) => (b.width, b.height),
+        None => return Err(NotationError::new(0, NotationErrorKind::InvalidBoard(String::new()))),
+    };
+    if initial_boards.iter().any(|b| b.width != width || b.height != height) {
+        return Err(NotationError::new(0, NotationErrorKind::InvalidBoard(String::from("boards differ in size"))));
+    }
+
+    let mut game = variants::from_boards(&initial_boards, even_initial_timelines);
+    let mut turns: Vec<Vec<Move>> = Vec::new();
+
+    for (line, raw_moveset) in split_movesets(&movetext) {
+        let mut moveset: Vec<Move> = Vec::with_capacity(raw_moveset.len());
+        for token in raw_moveset {
+            let raw_move = parse_move(token, game.info.active_player, even_initial_timelines)
+                .ok_or_else(|| NotationError::new(line, NotationErrorKind::InvalidMove(token.to_string())))?;
+            moveset.push(resolve_move(&game, &raw_move, token).map_err(|kind| NotationError::new(line, kind))?);
+        }
+        if let Err(kind) = replay::play_moveset(&mut game, &moveset) {
+            let err = ReplayError { turn: turns.len(), kind };
+            return Err(NotationError::new(line, NotationErrorKind::IllegalMoveset(err)));
+        }
+        turns.push(moveset);
+    }
+
+    Ok(GameRecord {
+        headers,
+        initial_boards,
+        width,
+        height,
+        even_initial_timelines,
+        turns,
+        game,
+    })
+}
+
+/**
+    Reads a moveset (moves separated by whitespace, for instance `(0T5)Nf3>>(0T3)g3 (1T4)Nh4`) for the active player of `game`.
+    The moves are only resolved against their board; use `replay::play_moveset` to check the moveset as a whole.
+**/
+pub fn parse_moveset(game: &Game, raw: &str) -> Result<Vec<Move>, NotationErrorKind> {
+    let even = game.info.even_initial_timelines;
+    raw.split_whitespace()
+        .map(|token| {
+            let raw_move = parse_move(token, game.info.active_player, even)
+                .ok_or_else(|| NotationErrorKind::InvalidMove(token.to_string()))?;
+            resolve_move(game, &raw_move, token)
+        })
+        .collect()
+}
+
+/// Reads a board's coordinates (`(LTn)`, the parentheses may be omitted); `white` selects which of the two boards of turn `n` is meant
+pub fn parse_board(raw: &str, white: bool, even_initial_timelines: bool) -> Option<(i32, isize)> {
+    let raw = raw.trim();
+    let wrapped = if raw.starts_with('(') { raw.to_string() } else { format!("({})", raw) };
+    match parse_board_prefix(&wrapped, white, even_initial_timelines)? {
+        (coords, "") => Some(coords),
+        _ => None,
+    }
+}
+
+/// Writes a board's coordinates, `(LTn)`
+pub fn write_board(board: &Board, even_initial_timelines: bool) -> String {
+    write_board_coordinates(board.l, board.t, even_initial_timelines)
+}
+
+/// Writes the coordinates of the board at `(l, t)`, `(LTn)`; `-0` and `+0` are written out if there are even initial timelines
+pub fn write_board_coordinates(l: i32, t: isize, even_initial_timelines: bool) -> String {
+    format!("({}T{})", write_timeline(l, even_initial_timelines), turn_number(t))
+}
+
+/// Returns the turn of the boards at time `t`, as numbered in-game: `t = 0` and `t = 1` are on turn 1, black's `t = -1` (Turn Zero) on turn 0
+pub fn turn_number(t: isize) -> isize {
+    t.div_euclid(2) + 1
+}
+
+/// Writes a record back out in 5dpgn; the turns are replayed (and checked) to tell branching jumps (`>>`) from non-branching ones (`>`)
+pub fn write(record: &GameRecord) -> Result<String, NotationError> {
+    let mut res = String::new();
+    let mut custom = true;
+
+    for (key, value) in &record.headers {
+        if key.eq_ignore_ascii_case("board") && !value.eq_ignore_ascii_case("custom") {
+            custom = false;
+        }
+        res.push_str(&format!("[{} \"{}\"]\n", key, value));
+    }
+    if custom {
+        for board in &record.initial_boards {
+            res.push_str(&format!("[{}]\n", write_fen(board, record.even_initial_timelines)));
+        }
+    }
+    res.push('\n');
+
+    let mut game = record.initial_game();
+    let mut turn = 1;
+    let mut turn_open = false;
+    for (n, moveset) in record.turns.iter().enumerate() {
+        let moves = moveset
+            .iter()
+            .map(|mv| write_move(&game, mv))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if game.info.active_player {
+            res.push_str(&format!("{}. {}", turn, moves));
+            turn_open = true;
+        } else {
+            if !turn_open {
+                res.push_str(&format!("{}.", turn));
+            }
+            res.push_str(&format!(" / {}\n", moves));
+            turn_open = false;
+            turn += 1;
+        }
+        if let Err(kind) = replay::play_moveset(&mut game, moveset) {
+            let err = ReplayError { turn: n, kind };
+            return Err(NotationError::new(0, NotationErrorKind::IllegalMoveset(err)));
+        }
+    }
+    if turn_open {
+        res.push('\n');
+    }
+
+    Ok(res)
+}
+
+/// Writes a single move as it would appear in a record; `game` must be the position right before the move's turn
+pub fn write_move(game: &Game, mv: &Move) -> String {
+    let even = game.info.even_initial_timelines;
+    let src_board = write_board_coordinates(mv.src.0, mv.src.1, even);
+    if mv.noop {
+        return String::new();
+    }
+    if mv.castle {
+        return format!("{}{}", src_board, if mv.castle_long { "O-O-O" } else { "O-O" });
+    }
+
+    let capture = if mv.dst_piece.is_blank() && mv.en_passant.is_none() { "" } else { "x" };
+    let promotion = match mv.promotion {
+        Some(piece) => format!("={}", piece.as_uppercase()),
+        None => String::new(),
+    };
+
+    if mv.src.0 == mv.dst.0 && mv.src.1 == mv.dst.1 {
+        if mv.src_piece.is_pawn() {
+            if capture.is_empty() {
+                format!("{}{}{}{}", src_board, write_file(mv.dst.2), mv.dst.3 + 1, promotion)
+            } else {
+                format!(
+                    "{}{}x{}{}{}",
+                    src_board,
+                    write_file(mv.src.2),
+                    write_file(mv.dst.2),
+                    mv.dst.3 + 1,
+                    promotion
+                )
+            }
+        } else {
+            format!(
+                "{}{}{}{}{}{}{}",
+                src_board,
+                mv.src_piece.as_uppercase(),
+                write_file(mv.src.2),
+                mv.src.3 + 1,
+                capture,
+                write_file(mv.dst.2),
+                mv.dst.3 + 1
+            )
+        }
+    } else {
+        let branching = game
+            .get_last_board(mv.dst.0)
+            .map(|b| b.t != mv.dst.1)
+            .unwrap_or(true);
+        format!(
+            "{}{}{}{}{}{}({}T{}){}{}{}",
+            src_board,
+            if mv.src_piece.is_pawn() { "" } else { mv.src_piece.as_uppercase() },
+            write_file(mv.src.2),
+            mv.src.3 + 1,
+            if branching { ">>" } else { ">" },
+            capture,
+            write_timeline(mv.dst.0, even),
+            turn_number(mv.dst.1),
+            write_file(mv.dst.2),
+            mv.dst.3 + 1,
+            promotion
+        )
+    }
+}
+
+/// Writes a board in the 5DFEN format used by custom positions, without the surrounding brackets
+pub fn write_fen(board: &Board, even_initial_timelines: bool) -> String {
+    let mut res = String::new();
+    for y in (0..board.height).rev() {
+        let mut blanks = 0;
+        for x in 0..board.width {
+            let piece = board.get_unsafe(x, y);
+            if piece.is_blank() {
+                blanks += 1;
+            } else {
+                if blanks > 0 {
+                    res.push_str(&blanks.to_string());
+                    blanks = 0;
+                }
+                res.push_str(&piece.to_string());
+            }
+        }
+        if blanks > 0 {
+            res.push_str(&blanks.to_string());
+        }
+        if y > 0 {
+            res.push('/');
+        }
+    }
+    let l = write_timeline(board.l, even_initial_timelines);
+    format!(
+        "{}:{}:{}:{}",
+        res,
+        l,
+        turn_number(board.t),
+        if board.active_player() { "w" } else { "b" }
+    )
+}
+
+/**
+    Reads a single board in the 5DFEN format; its timeline index is read like `parse_timeline` does.
+    Whether or not a position has even initial timelines depends on all of its boards (see `fen_even_timelines`).
+**/
+pub fn parse_fen(raw: &str, even_initial_timelines: bool) -> Option<Board> {
+    let mut fields = raw.trim().split(':');
+    let rows = fields.next()?.split('/').collect::<Vec<_>>();
+    let l = parse_timeline(fields.next()?, even_initial_timelines)?;
+    let turn = fields.next()?.trim().parse::<isize>().ok()?;
+    let white = match fields.next()?.trim() {
+        "w" => true,
+        "b" => false,
+        _ => return None,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+
+    let mut pieces: Vec<Vec<Piece>> = Vec::with_capacity(rows.len());
+    for row in rows.iter() {
+        let mut res = Vec::new();
+        let mut chars = row.chars().peekable();
+        while let Some(c) = chars.next() {
+            if let Some(digit) = c.to_digit(10) {
+                let mut n = digit as usize;
+                while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    n = n * 10 + d as usize;
+                    chars.next();
+                }
+                res.extend(std::iter::repeat(Piece::Blank).take(n));
+            } else if c == '*' {
+                // Unmoved marker: castling rights are derived from the starting position instead
+                continue;
+            } else {
+                res.push(piece_from_char(c.to_ascii_uppercase(), c.is_ascii_uppercase())?);
+            }
+        }
+        pieces.push(res);
+    }
+
+    let height = pieces.len();
+    let width = pieces.first()?.len();
+    if width == 0 || width > 23 || height > 255 || pieces.iter().any(|row| row.len() != width) {
+        return None;
+    }
+
+    let t = (turn - 1) * 2 + if white { 0 } else { 1 };
+    let mut board = Board::new(t, l, width as u8, height as u8);
+    for (row, y) in pieces.into_iter().zip((0..height).rev()) {
+        for (x, piece) in row.into_iter().enumerate() {
+            board.set_unsafe(x as u8, y as u8, piece);
+        }
+    }
+
+    Some(board)
+}
+
+/// Tells whether or not the 5DFEN boards of a position are on even initial timelines, which is the case if any of them is on `+0` or `-0`
+pub fn fen_even_timelines<'a>(boards: impl IntoIterator<Item = &'a str>) -> bool {
+    boards
+        .into_iter()
+        .any(|raw| matches!(raw.trim().split(':').nth(1).map(str::trim), Some("+0") | Some("-0")))
+}
+
+/// Parses a timeline index as written in-game; `-0` maps to `-1` and `-1` to `-2` if there are even initial timelines
+pub fn parse_timeline(raw: &str, even_initial_timelines: bool) -> Option<i32> {
+    let raw = raw.trim();
+    let n = raw.trim_start_matches(['+', '-']).parse::<i32>().ok()?;
+    if raw.starts_with('-') {
+        if even_initial_timelines {
+            Some(-n - 1)
+        } else {
+            Some(-n)
+        }
+    } else {
+        Some(n)
+    }
+}
+
+/// Converts the uppercase letter of a piece into a `Piece`
+pub fn piece_from_char(c: char, white: bool) -> Option<Piece> {
+    Some(match (c, white) {
+        ('P', true) => Piece::PawnW,
+        ('P', false) => Piece::PawnB,
+        ('N', true) => Piece::KnightW,
+        ('N', false) => Piece::KnightB,
+        ('B', true) => Piece::BishopW,
+        ('B', false) => Piece::BishopB,
+        ('R', true) => Piece::RookW,
+        ('R', false) => Piece::RookB,
+        ('Q', true) => Piece::QueenW,
+        ('Q', false) => Piece::QueenB,
+        ('K', true) => Piece::KingW,
+        ('K', false) => Piece::KingB,
+        ('U', true) => Piece::UnicornW,
+        ('U', false) => Piece::UnicornB,
+        ('D', true) => Piece::DragonW,
+        ('D', false) => Piece::DragonB,
+        ('S', true) => Piece::PrincessW,
+        ('S', false) => Piece::PrincessB,
+        ('W', true) => Piece::BrawnW,
+        ('W', false) => Piece::BrawnB,
+        ('C', true) => Piece::CommonKingW,
+        ('C', false) => Piece::CommonKingB,
+        ('Y', true) => Piece::RoyalQueenW,
+        ('Y', false) => Piece::RoyalQueenB,
+        _ => return None,
+    })
+}
+
+/// Splits the movetext into movesets (one per player and per turn), along with the line at which each moveset begins
+fn split_movesets<'a>(movetext: &[(usize, &'a str)]) -> Vec<(usize, Vec<&'a str>)> {
+    let mut res: Vec<(usize, Vec<&'a str>)> = Vec::new();
+    let mut current: Vec<&'a str> = Vec::new();
+    let mut current_line = 0;
+    let mut comment_depth = 0usize;
+
+    for (line, text) in movetext {
+        for token in text.split_whitespace() {
+            // Comments: `{...}`, possibly spanning several lines
+            if comment_depth > 0 || token.starts_with('{') {
+                comment_depth += token.matches('{').count();
+                comment_depth = comment_depth.saturating_sub(token.matches('}').count());
+                continue;
+            }
+
+            let mut token = token;
+            // Turn numbers: `12.`, possibly glued to the first move
+            let digits = token.chars().take_while(|c| c.is_ascii_digit()).count();
+            if digits > 0 && token[digits..].starts_with('.') {
+                if !current.is_empty() {
+                    res.push((current_line, std::mem::take(&mut current)));
+                }
+                token = token[digits..].trim_start_matches('.');
+                if token.is_empty() {
+                    continue;
+                }
+            }
+
+            if token == "/" {
+                if !current.is_empty() {
+                    res.push((current_line, std::mem::take(&mut current)));
+                }
+            } else if token == "1-0" || token == "0-1" || token == "1/2-1/2" || token == "*" {
+                // Game result
+                continue;
+            } else if token.starts_with("(~") || token.starts_with("(>") {
+                // Present and timeline annotations
+                continue;
+            } else {
+                if current.is_empty() {
+                    current_line = *line;
+                }
+                current.push(token);
+            }
+        }
+    }
+    if !current.is_empty() {
+        res.push((current_line, current));
+    }
+
+    res
+}
+
+/// Reads a board prefix, `(LTn)`, and returns it along with the rest of the string
+fn parse_board_prefix(raw: &str, white: bool, even: bool) -> Option<((i32, isize), &str)> {
+    if !raw.starts_with('(') {
+        return None;
+    }
+    let end = raw.find(')')?;
+    let inner = &raw[1..end];
+    let t_index = inner.find('T')?;
+    let l = parse_timeline(&inner[..t_index], even)?;
+    let turn = inner[t_index + 1..].parse::<isize>().ok()?;
+    let t = (turn - 1) * 2 + if white { 0 } else { 1 };
+    Some(((l, t), &raw[end + 1..]))
+}
+
+/// Reads a square (`e4`) at the end of `raw`; returns the square and what precedes it
+fn parse_square_end(raw: &str) -> Option<((u8, u8), &str)> {
+    let digits = raw.chars().rev().take_while(|c| c.is_ascii_digit()).count();
+    if digits == 0 || digits >= raw.len() {
+        return None;
+    }
+    let rank = raw[raw.len() - digits..].parse::<u8>().ok()?.checked_sub(1)?;
+    let file_char = raw[..raw.len() - digits].chars().last()?;
+    if !('a'..='w').contains(&file_char) {
+        return None;
+    }
+    let file = file_char as u8 - b'a';
+    Some(((file, rank), &raw[..raw.len() - digits - 1]))
+}
+
+/// Reads a single move token
+fn parse_move(token: &str, white: bool, even: bool) -> Option<RawMove> {
+    let token = token.trim_end_matches(['+', '#', '*', '!', '?', '~']);
+    let (src_board, rest) = parse_board_prefix(token, white, even)?;
+
+    if rest == "O-O" || rest == "O-O-O" || rest == "0-0" || rest == "0-0-0" {
+        return Some(RawMove {
+            src_board,
+            piece: Some('K'),
+            src_file: None,
+            src_rank: None,
+            dst_board: None,
+            dst: (0, 0),
+            castle: Some(rest.len() > 3),
+            promotion: None,
+        });
+    }
+
+    let (rest, promotion) = match rest.find('=') {
+        Some(index) => {
+            let mut chars = rest[index + 1..].chars();
+            let promotion = chars.next()?;
+            if chars.next().is_some() {
+                return None;
+            }
+            (&rest[..index], Some(promotion))
+        }
+        None => (rest, None),
+    };
+
+    let (piece, rest) = match rest.chars().next() {
+        Some(c) if c.is_ascii_uppercase() => (Some(c), &rest[1..]),
+        _ => (None, rest),
+    };
+
+    let (src_part, dst_board, dst_part) = if let Some(index) = rest.find('>') {
+        let src_part = &rest[..index];
+        let after = rest[index + 1..].trim_start_matches('>').trim_start_matches('x');
+        let (dst_board, after) = parse_board_prefix(after, white, even)?;
+        (src_part, Some(dst_board), after.trim_start_matches('x'))
+    } else {
+        (&rest[..0], None, rest)
+    };
+
+    let (dst, disambiguation) = if dst_board.is_some() {
+        let (dst, remainder) = parse_square_end(dst_part)?;
+        if !remainder.is_empty() {
+            return None;
+        }
+        (dst, src_part)
+    } else {
+        parse_square_end(dst_part)?
+    };
+    let disambiguation = disambiguation.trim_end_matches('x');
+
+    let mut src_file = None;
+    let mut src_rank = None;
+    for (i, c) in disambiguation.char_indices() {
+        if ('a'..='w').contains(&c) && i == 0 {
+            src_file = Some(c as u8 - b'a');
+        } else if c.is_ascii_digit() {
+            let rank = disambiguation[i..].parse::<u8>().ok()?.checked_sub(1)?;
+            src_rank = Some(rank);
+            break;
+        } else {
+            return None;
+        }
+    }
+
+    Some(RawMove {
+        src_board,
+        piece,
+        src_file,
+        src_rank,
+        dst_board,
+        dst,
+        castle: None,
+        promotion,
+    })
+}
+
+/// Finds the move described by `raw` among the moves that can be made on its board
+fn resolve_move(game: &Game, raw: &RawMove, token: &str) -> Result<Move, NotationErrorKind> {
+    let board = game
+        .get_board(raw.src_board.0, raw.src_board.1)
+        .ok_or_else(|| NotationErrorKind::NoSuchMove(token.to_string()))?;
+
+    // Promotions default to a queen
+    let promotion = match raw.promotion {
+        Some(c) => match piece_from_char(c, board.active_player()) {
+            Some(piece) if promotion_pieces(game, board.active_player()).contains(&piece) => Some(piece),
+            _ => return Err(NotationErrorKind::UnsupportedPromotion(token.to_string())),
+        },
+        None => None,
+    };
+
+    let dst = match raw.dst_board {
+        Some((l, t)) => (l, t, raw.dst.0, raw.dst.1),
+        None => (board.l, board.t, raw.dst.0, raw.dst.1),
+    };
+
+    let candidates = probable_moves(game, board, &vec![])
+        .into_iter()
+        .filter(|mv| match raw.castle {
+            Some(long) => mv.castle && mv.castle_long == long,
+            None => {
+                !mv.castle
+                    && mv.dst == dst
+                    && match raw.piece {
+                        Some(p) => mv.src_piece.as_uppercase().starts_with(p),
+                        None => mv.src_piece.is_pawn(),
+                    }
+                    && raw.src_file.map(|x| x == mv.src.2).unwrap_or(true)
+                    && raw.src_rank.map(|y| y == mv.src.3).unwrap_or(true)
+                    && match (promotion, mv.promotion) {
+                        (Some(p), Some(q)) => p == q,
+                        (None, Some(q)) => q.as_uppercase() == "Q",
+                        (Some(_), None) => false,
+                        (None, None) => true,
+                    }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    match candidates.len() {
+        0 => Err(NotationErrorKind::NoSuchMove(token.to_string())),
+        1 => Ok(candidates[0]),
+        _ => Err(NotationErrorKind::AmbiguousMove(token.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `raw`, writes it back and parses the result again; both records must hold the same game
+    fn round_trip(raw: &str) -> GameRecord {
+        let record = parse(raw).unwrap_or_else(|err| panic!("Couldn't parse the record: {}", err));
+        let written = write(&record).unwrap();
+        let reparsed = parse(&written).unwrap_or_else(|err| panic!("Couldn't parse the written record: {}\n{}", err, written));
+
+        assert_eq!(reparsed.headers, record.headers);
+        assert_eq!(reparsed.turns, record.turns);
+        assert_eq!(reparsed.even_initial_timelines, record.even_initial_timelines);
+        assert_eq!(fens(&reparsed.initial_boards, reparsed.even_initial_timelines), fens(&record.initial_boards, record.even_initial_timelines));
+        assert_eq!(reparsed.game.hash, record.game.hash);
+        assert_eq!(write(&reparsed).unwrap(), written);
+        reparsed
+    }
+
+    fn fens(boards: &[Board], even_initial_timelines: bool) -> Vec<String> {
+        boards.iter().map(|b| write_fen(b, even_initial_timelines)).collect()
+    }
+
+    fn written_moves(record: &GameRecord) -> String {
+        let written = write(record).unwrap();
+        written.lines().filter(|line| !line.starts_with('[') && !line.is_empty()).collect::<Vec<_>>().join("\n")
+    }
+
+    #[test]
+    fn round_trips_castling() {
+        let record = round_trip(
+            "[Board \"custom\"]\n[r3k2r/8/8/8/8/8/8/R3K2R:0:1:w]\n\n1. (0T1)O-O / (0T1)O-O-O\n",
+        );
+        assert!(record.turns.iter().all(|moveset| moveset[0].castle));
+        let board = record.game.get_last_board(0).unwrap();
+        assert_eq!(board.get(6, 0), Some(Piece::KingW));
+        assert_eq!(board.get(5, 0), Some(Piece::RookW));
+        assert_eq!(board.get(2, 7), Some(Piece::KingB));
+        assert_eq!(board.get(3, 7), Some(Piece::RookB));
+        assert_eq!(written_moves(&record), "1. (0T1)O-O / (0T1)O-O-O");
+    }
+
+    #[test]
+    fn round_trips_jumps() {
+        // Branching jump, back to the first board
+        let record = round_trip("[Board \"Standard\"]\n\n1. (0T1)Ng1f3 / (0T1)Ng8f6\n2. (0T2)Nf3>>(0T1)f5 / (1T1)Nb8c6\n");
+        assert_eq!(record.game.timelines.len(), 2);
+        assert!(written_moves(&record).contains("(0T2)Nf3>>(0T1)f5"));
+
+        // Non-branching jump, between the two timelines of the same turn
+        let record = round_trip("[Board \"Standard - Two Timelines\"]\n\n1. (-0T1)Ng1>(+0T1)g3 / (-0T1)Ng8f6 (+0T1)Ng8f6\n");
+        assert_eq!(record.game.timelines.len(), 2);
+        assert!(written_moves(&record).starts_with("1. (-0T1)Ng1>(+0T1)g3"));
+    }
+
+    #[test]
+    fn round_trips_even_timelines() {
+        let record = round_trip(
+            "[Board \"custom\"]\n[4k3/8/8/8/8/8/8/4K3:-0:1:w]\n[4k3/8/8/8/8/8/8/4K3:+0:1:w]\n\n1. (-0T1)Kd1 (+0T1)Kf1 / (-0T1)Kd8 (+0T1)Kf8\n",
+        );
+        assert!(record.even_initial_timelines);
+        assert!(record.game.get_board(-1, 2).is_some());
+        assert!(record.game.get_board(0, 2).is_some());
+        let written = write(&record).unwrap();
+        assert!(written.contains(":-0:1:w]") && written.contains(":+0:1:w]"));
+        assert_eq!(written_moves(&record), "1. (-0T1)Ke1d1 (+0T1)Ke1f1 / (-0T1)Ke8d8 (+0T1)Ke8f8");
+    }
+
+    #[test]
+    fn reads_even_timelines_from_every_board() {
+        // `-1` comes before the boards telling that the timelines are even, and must still be read as the one below `-0`
+        let record = round_trip(
+            "[Board \"custom\"]\n[4k3/8/8/8/8/8/8/4K3:-1:1:w]\n[4k3/8/8/8/8/8/8/4K3:-0:1:w]\n[4k3/8/8/8/8/8/8/4K3:+0:1:w]\n\n1. (-1T1)Kd1 (-0T1)Kd1 (+0T1)Kf1\n",
+        );
+        assert!(record.even_initial_timelines);
+        let mut timelines = record.game.timelines.keys().copied().collect::<Vec<_>>();
+        timelines.sort_unstable();
+        assert_eq!(timelines, vec![-2, -1, 0]);
+        assert_eq!(written_moves(&record), "1. (-1T1)Ke1d1 (-0T1)Ke1d1 (+0T1)Ke1f1");
+    }
+
+    #[test]
+    fn round_trips_turn_zero() {
+        // Black's starting board is written `T0`, and can be jumped to
+        let record = round_trip("[Board \"Standard - Turn Zero\"]\n\n1. (0T1)e3 / (0T1)Ng8>>(0T0)g6\n");
+        assert_eq!(record.turns[1][0].dst.1, -1);
+        assert_eq!(record.game.timelines.len(), 2);
+        assert_eq!(write_board_coordinates(0, -1, false), "(0T0)");
+        assert!(written_moves(&record).ends_with("/ (0T1)Ng8>>(0T0)g6"));
+    }
+
+    #[test]
+    fn round_trips_promotions() {
+        let record = round_trip("[Board \"custom\"]\n[1n2k3/P1P5/8/8/8/8/8/4K3:0:1:w]\n\n1. (0T1)a8=N / (0T1)Ke7\n2. (0T2)cxb8\n");
+        assert_eq!(record.turns[0][0].promotion, Some(Piece::KnightW));
+        // Promotions default to a queen, and are always written out
+        assert_eq!(record.turns[2][0].promotion, Some(Piece::QueenW));
+        assert_eq!(written_moves(&record), "1. (0T1)a8=N / (0T1)Ke8e7\n2. (0T2)cxb8=Q");
+    }
+
+    #[test]
+    fn skips_comments_and_annotations() {
+        let plain = parse("[Board \"Standard\"]\n\n1. (0T1)e3 / (0T1)e6\n2. (0T2)Qh5 / (0T2)Nc6\n").unwrap();
+        let commented = round_trip(
+            "[Board \"Standard\"]\n{A comment before the first turn}\n1.(0T1)e3 {a comment\nspanning {nested} lines} / (0T1)e6 (~T2)\n2. (0T2)Qh5! / (0T2)Nc6?? 1/2-1/2\n",
+        );
+        assert_eq!(commented.turns, plain.turns);
+        assert_eq!(commented.game.hash, plain.game.hash);
+    }
+
+    #[test]
+    fn reports_invalid_moves() {
+        let err = parse("[Board \"Standard\"]\n\n1. (0T1)e3 / (0T1)e6\n2. (0T2)e5\n").unwrap_err();
+        assert_eq!(err, NotationError::new(4, NotationErrorKind::NoSuchMove(String::from("(0T2)e5"))));
+        let err = parse("[Board \"Nonexistent\"]\n").unwrap_err();
+        assert_eq!(err.kind, NotationErrorKind::UnknownVariant(String::from("Nonexistent")));
+    }
+}
@@ -1,5 +1,7 @@
 use super::game;
+use crate::error::{Error, Result};
 use serde::Deserialize;
+use std::convert::TryFrom;
 
 #[derive(Debug, Deserialize)]
 struct GameRaw {
@@ -20,8 +22,9 @@ struct TimelineRaw {
     emerges_from: Option<f32>,
 }
 
-pub fn parse(raw: &str) -> Option<game::Game> {
-    let game_raw: GameRaw = serde_json::from_str(raw).ok()?;
+/// Reads a game outputted by 5dchess-notation's JSON exporter
+pub fn parse(raw: &str) -> Result<game::Game> {
+    let game_raw: GameRaw = serde_json::from_str(raw).map_err(|err| Error::Json(err.to_string()))?;
 
     let even_initial_timelines = game_raw
         .timelines
@@ -31,11 +34,13 @@ pub fn parse(raw: &str) -> Option<game::Game> {
     let min_timeline = game_raw.timelines
         .iter()
         .map(|tl| tl.index)
-        .min_by_key(|x| (*x) as isize)?;
+        .min_by_key(|x| (*x) as isize)
+        .ok_or(Error::NoTimeline)?;
     let max_timeline = game_raw.timelines
         .iter()
         .map(|tl| tl.index)
-        .max_by_key(|x| (*x) as isize)?;
+        .max_by_key(|x| (*x) as isize)
+        .ok_or(Error::NoTimeline)?;
 
     let timeline_width = ((-min_timeline).min(max_timeline) + 1.0).round();
     let active_timelines = game_raw.timelines
@@ -43,53 +48,64 @@ pub fn parse(raw: &str) -> Option<game::Game> {
         .filter(|tl| tl.index.abs() <= timeline_width);
     let present = active_timelines
         .map(|tl| tl.begins_at + (tl.states.len() as isize) - 1)
-        .min()?;
+        .min()
+        .ok_or(Error::NoTimeline)?;
 
     let mut res = game::Game::new(game_raw.width, game_raw.height);
 
     res.info.present = present;
-    res.info.min_timeline = de_l(min_timeline, even_initial_timelines);
-    res.info.max_timeline = de_l(max_timeline, even_initial_timelines);
+    res.info.min_timeline = de_l(min_timeline, even_initial_timelines)?;
+    res.info.max_timeline = de_l(max_timeline, even_initial_timelines)?;
     res.info.active_player = game_raw.active_player;
     res.info.even_initial_timelines = even_initial_timelines;
 
     for tl in game_raw.timelines.into_iter() {
         res.timelines.insert(
-            de_l(tl.index, even_initial_timelines),
-            de_timeline(tl, even_initial_timelines),
+            de_l(tl.index, even_initial_timelines)?,
+            de_timeline(tl, even_initial_timelines)?,
         );
     }
+    res.rehash();
 
-    Some(res)
+    Ok(res)
 }
 
-fn de_board(raw: Vec<usize>, t: isize, l: i32, width: u8, height: u8) -> game::Board {
+fn de_board(raw: Vec<usize>, t: isize, l: i32, width: u8, height: u8) -> Result<game::Board> {
+    if raw.len() != width as usize * height as usize {
+        return Err(Error::InvalidBoardSize(l, t));
+    }
     let mut res = game::Board::new(t, l, width, height);
-    res.pieces = raw
-        .into_iter()
-        .map(|x| game::Piece::from(x))
-        .collect();
-    res
+    for (index, raw_piece) in raw.into_iter().enumerate() {
+        res.set_unsafe((index % width as usize) as u8, (index / width as usize) as u8, game::Piece::try_from(raw_piece)?);
+    }
+    Ok(res)
 }
 
-fn de_l(raw: f32, even: bool) -> i32 {
-    if even && raw < 0.0 {
-        (raw.ceil() - 1.0) as i32
+/// Converts a timeline index; only the initial timelines may have a half-integer index (`±0.5`), if there is an even amount of them
+fn de_l(raw: f32, even: bool) -> Result<i32> {
+    if raw.fract() != 0.0 && !(even && raw.abs() == 0.5) {
+        Err(Error::InvalidTimeline(raw))
+    } else if even && raw < 0.0 {
+        Ok((raw.ceil() - 1.0) as i32)
     } else {
-        raw.floor() as i32
+        Ok(raw.floor() as i32)
     }
 }
 
-fn de_timeline(raw: TimelineRaw, even: bool) -> game::Timeline {
+fn de_timeline(raw: TimelineRaw, even: bool) -> Result<game::Timeline> {
+    let emerges_from = match raw.emerges_from {
+        Some(x) => Some(de_l(x, even)?),
+        None => None,
+    };
     let mut res = game::Timeline::new(
-        de_l(raw.index, even),
+        de_l(raw.index, even)?,
         raw.width,
         raw.height,
         raw.begins_at,
-        raw.emerges_from.map(|x| de_l(x, even)),
+        emerges_from,
     );
 
-    let index = de_l(raw.index, even);
+    let index = de_l(raw.index, even)?;
     let begins_at = raw.begins_at;
     let width = raw.width;
     let height = raw.height;
@@ -99,7 +115,7 @@ fn de_timeline(raw: TimelineRaw, even: bool) -> game::Timeline {
         .into_iter()
         .enumerate()
         .map(|(i, b)| de_board(b, begins_at + i as isize, index, width, height))
-        .collect();
+        .collect::<Result<Vec<_>>>()?;
 
-    res
+    Ok(res)
 }
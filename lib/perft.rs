@@ -0,0 +1,145 @@
+/*
+    Perft: exhaustively counts the legal movesets that can be played from a position, ply after ply.
+
+    The counts can be compared against those of other implementations to catch move generation regressions
+    (in `probable_moves`, `Move::generate_vboards` or `MovesetIter`).
+*/
+
+use crate::{game::*, moves::*, replay};
+use std::collections::HashSet;
+
+/// The result of `perft`
+#[derive(Debug, Clone)]
+pub struct Perft {
+    /// `nodes[n]` is the number of sequences of `n + 1` legal movesets
+    pub nodes: Vec<usize>,
+    /// Every legal first moveset, along with the number of sequences of `depth` movesets that begin with it
+    pub divide: Vec<(Vec<Move>, usize)>,
+}
+
+/**
+    Counts the sequences of legal movesets of length 1 to `depth` that can be played from `game`.
+    Movesets that only differ by the order of their moves, yet lead to the same boards, are only counted once.
+
+    The movesets are played on `game` and undone afterwards; `game` is left as it was found, `redo_stack` included.
+
+    ```
+    let res = perft(&mut game, 2);
+    println!("{:?}", res.nodes); // [20, 400] for a standard game
+    ```
+**/
+pub fn perft(game: &mut Game, depth: usize) -> Perft {
+    let mut res = Perft {
+        nodes: vec![0; depth],
+        divide: Vec::new(),
+    };
+    if depth == 0 {
+        return res;
+    }
+
+    let redo_stack = std::mem::take(&mut game.redo_stack);
+
+    for moveset in movesets(game) {
+        if let Err(kind) = replay::play_moveset(game, &moveset) {
+            warn!("perft: moveset {:?} was yielded by legal_movesets but is illegal: {}", moveset, kind);
+            continue;
+        }
+        let before = res.nodes[depth - 1];
+        count(game, depth, 1, &mut res.nodes);
+        res.divide.push((moveset, res.nodes[depth - 1] - before));
+        game.undo();
+    }
+
+    game.redo_stack = redo_stack;
+    res
+}
+
+/// Counts the movesets that can be played at ply `ply`, once `game` reached it, and recurses until `depth`
+fn count(game: &mut Game, depth: usize, ply: usize, nodes: &mut Vec<usize>) {
+    nodes[ply - 1] += 1;
+    if ply >= depth {
+        return;
+    }
+
+    for moveset in movesets(game) {
+        if let Err(kind) = replay::play_moveset(game, &moveset) {
+            warn!("perft: moveset {:?} was yielded by legal_movesets but is illegal: {}", moveset, kind);
+            continue;
+        }
+        count(game, depth, ply + 1, nodes);
+        game.undo();
+    }
+}
+
+/// Returns every legal moveset of the active player, without the no-op moves and without duplicates
+fn movesets(game: &Game) -> Vec<Vec<Move>> {
+    let virtual_boards: Vec<&Board> = Vec::new();
+    let mut seen: HashSet<Vec<(i32, isize, Vec<usize>)>> = HashSet::new();
+
+    legal_movesets(game, &game.info, &virtual_boards, 0, 0)
+        .filter_map(|(moves, boards, _info, _score)| {
+            let mut key = boards
+                .iter()
+                .map(|b| (b.l, b.t, b.pieces().map(usize::from).collect::<Vec<_>>()))
+                .collect::<Vec<_>>();
+            key.sort();
+            if seen.insert(key) {
+                Some(moves.into_iter().filter(|mv| !mv.noop).collect())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variants::Variant;
+
+    fn nodes(variant: Variant, depth: usize) -> Vec<usize> {
+        let mut game = variant.game();
+        let hash = game.hash;
+        let res = perft(&mut game, depth);
+        assert_eq!(game.hash, hash, "perft didn't leave the game as it found it");
+        assert_eq!(res.divide.iter().map(|(_moveset, count)| count).sum::<usize>(), res.nodes[depth - 1]);
+        res.nodes
+    }
+
+    #[test]
+    fn standard() {
+        assert_eq!(nodes(Variant::Standard, 1), vec![20]);
+        assert_eq!(nodes(Variant::Standard, 2), vec![20, 400]);
+    }
+
+    #[test]
+    fn turn_zero() {
+        assert_eq!(nodes(Variant::TurnZero, 1), vec![20]);
+        assert_eq!(nodes(Variant::TurnZero, 2), vec![20, 440]);
+    }
+
+    #[test]
+    fn very_small() {
+        assert_eq!(nodes(Variant::VerySmall, 1), vec![9]);
+        assert_eq!(nodes(Variant::VerySmall, 2), vec![9, 77]);
+    }
+
+    #[test]
+    fn simple_variants() {
+        assert_eq!(nodes(Variant::KnightsVsBishops, 2), vec![16, 384]);
+        // Both castlings are open in Simple Set, and the short one in Focused
+        assert_eq!(nodes(Variant::SimpleSet, 2), vec![25, 625]);
+        assert_eq!(nodes(Variant::Focused, 2), vec![24, 576]);
+    }
+
+    #[test]
+    fn small() {
+        assert_eq!(nodes(Variant::Small, 2), vec![6, 35]);
+    }
+
+    #[test]
+    fn timeline_invasion() {
+        // One move on each timeline (20 x 5), or a jump from the -0 army onto +0, alone or after one of the king's 5 moves (20 x 6)
+        assert_eq!(nodes(Variant::TimelineInvasion, 1), vec![220]);
+    }
+}
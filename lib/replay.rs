@@ -0,0 +1,197 @@
+// Replays movesets on a game, one turn after the other
+
+use crate::{game::*, moves::*, notation, status::{self, Check}};
+use std::fmt;
+
+/// An error encountered while replaying a list of movesets; `turn` is the index of the offending moveset
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayError {
+    pub turn: usize,
+    pub kind: ReplayErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayErrorKind {
+    /// The moveset contains no move
+    EmptyMoveset,
+    /// The `n`-th move of the moveset moves a piece that doesn't belong to the active player
+    WrongPlayer(usize, Move),
+    /// The `n`-th move of the moveset cannot be made from its board
+    IllegalMove(usize, Move),
+    /// The `n`-th move of the moveset conflicts with the previous ones (the board was already played on, the board isn't the last of its timeline, etc.)
+    InvalidMove(usize, Move),
+    /// The moveset leaves the active player in check; holds every piece giving check
+    Check(Vec<Check>),
+    /// Some of the mandatory boards weren't played on; holds the `(l, t)` coordinates of these boards, and whether the game has even initial timelines
+    BoardsNotPlayed(Vec<(i32, isize)>, bool),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "moveset #{}: {}", self.turn + 1, self.kind)
+    }
+}
+
+impl fmt::Display for ReplayErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayErrorKind::EmptyMoveset => write!(f, "empty moveset"),
+            ReplayErrorKind::WrongPlayer(n, mv) => write!(f, "move #{} ({:?}) moves an opponent's piece", n + 1, mv),
+            ReplayErrorKind::IllegalMove(n, mv) => write!(f, "move #{} ({:?}) cannot be made", n + 1, mv),
+            ReplayErrorKind::InvalidMove(n, mv) => write!(f, "move #{} ({:?}) conflicts with the rest of the moveset", n + 1, mv),
+            ReplayErrorKind::Check(checks) => write!(
+                f,
+                "the king is left in check by {}",
+                checks.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", ")
+            ),
+            ReplayErrorKind::BoardsNotPlayed(boards, even_initial_timelines) => write!(
+                f,
+                "boards {} must still be played on",
+                boards
+                    .iter()
+                    .map(|(l, t)| notation::write_board_coordinates(*l, *t, *even_initial_timelines))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/**
+    Replays `movesets` on `game`, checking the legality of each of them; returns the final position or the first illegal moveset.
+
+    ```
+    let game = replay(game, vec![vec![mv_white], vec![mv_black]])?;
+    ```
+**/
+pub fn replay(mut game: Game, movesets: Vec<Vec<Move>>) -> Result<Game, ReplayError> {
+    for (turn, moveset) in movesets.into_iter().enumerate() {
+        play_moveset(&mut game, &moveset).map_err(|kind| ReplayError { turn, kind })?;
+    }
+    Ok(game)
+}
+
+/// Checks the legality of `moveset` and plays it on `game`; `game` is left untouched if the moveset is illegal
+pub fn play_moveset(game: &mut Game, moveset: &[Move]) -> Result<(), ReplayErrorKind> {
+    let (info, boards) = validate_moveset(game, moveset)?;
+    commit(game, info, boards);
+    Ok(())
+}
+
+/**
+    Checks the legality of `moveset` without playing it; returns the resulting `GameInfo` and boards,
+    or the reason why the moveset was rejected (the offending move, the pieces giving check or the boards left to play on).
+**/
+pub fn validate_moveset(game: &Game, moveset: &[Move]) -> Result<(GameInfo, Vec<Board>), ReplayErrorKind> {
+    if moveset.is_empty() {
+        return Err(ReplayErrorKind::EmptyMoveset);
+    }
+
+    let mut info = game.info;
+    let mut boards: Vec<Board> = Vec::new();
+
+    for (n, mv) in moveset.iter().enumerate() {
+        let board = match game.get_board(mv.src.0, mv.src.1) {
+            Some(board) => board,
+            None => return Err(ReplayErrorKind::InvalidMove(n, *mv)),
+        };
+        if board.active_player() != info.active_player || !mv.src_piece.is_own_piece(info.active_player) {
+            return Err(ReplayErrorKind::WrongPlayer(n, *mv));
+        }
+        if !probable_moves(game, board, &vec![]).contains(mv) {
+            return Err(ReplayErrorKind::IllegalMove(n, *mv));
+        }
+
+        match mv.generate_vboards(game, &info, &vec![], &boards) {
+            Some((new_info, mut new_boards)) => {
+                boards.append(&mut new_boards);
+                // The first move whose boards can't be appended along with the previous ones is the conflicting one
+                if game.check_boards(&boards).is_err() {
+                    return Err(ReplayErrorKind::InvalidMove(n, *mv));
+                }
+                info = new_info;
+            }
+            None => return Err(ReplayErrorKind::InvalidMove(n, *mv)),
+        }
+    }
+
+    {
+        let merged_vboards: Vec<&Board> = boards.iter().collect();
+
+        // The boards created by the moveset are among the opponent's boards
+        let checks = status::attacks(
+            game,
+            &merged_vboards,
+            get_opponent_boards(game, &merged_vboards, &info).into_iter(),
+        );
+        if !checks.is_empty() {
+            return Err(ReplayErrorKind::Check(checks));
+        }
+
+        if !all_boards_played(game, &merged_vboards, &info) {
+            // Same boards as the ones that `all_boards_played` looks for
+            let not_played = get_own_boards(game, &merged_vboards, &info)
+                .into_iter()
+                .filter(|b| b.t <= info.present)
+                .map(|b| (b.l, b.t))
+                .collect::<Vec<_>>();
+            return Err(ReplayErrorKind::BoardsNotPlayed(not_played, game.info.even_initial_timelines));
+        }
+    }
+
+    Ok((info, boards))
+}
+
+/// Returns the time coordinate of the earliest last board among the active timelines
+pub fn present(game: &Game) -> isize {
+    find_present(game, &vec![], &game.info)
+}
+
+/// Appends the boards of a moveset, checked by `validate_moveset`, to `game` and hands the turn over to the opponent
+fn commit(game: &mut Game, mut info: GameInfo, boards: Vec<Board>) {
+    game.commit_checked_moves(boards);
+    info.active_player = game.info.active_player;
+    game.info = info;
+    game.info.present = present(game);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{notation, variants::Variant};
+
+    #[test]
+    fn reports_the_conflicting_move() {
+        let record = notation::parse(
+            "[Board \"custom\"]\n[4k3/8/8/8/8/8/8/1N2K3:-1:1:w]\n[4k3/8/8/8/8/8/8/4K3:0:1:w]\n[4k3/8/8/8/8/8/8/4K3:1:1:w]\n",
+        );
+        let mut game = record.unwrap().game;
+        // The king moves on a board that the jump already played on
+        let moveset = notation::parse_moveset(&game, "(-1T1)Nb1>(0T1)b3 (0T1)Kd1 (1T1)Kd1").unwrap();
+        assert_eq!(play_moveset(&mut game, &moveset), Err(ReplayErrorKind::InvalidMove(1, moveset[1])));
+        assert!(game.get_board(0, 1).is_none());
+    }
+
+    #[test]
+    fn writes_even_timelines_in_errors() {
+        let mut game = Variant::TwoTimelines.game();
+        let moveset = notation::parse_moveset(&game, "(+0T1)e3").unwrap();
+        let err = play_moveset(&mut game, &moveset).unwrap_err();
+        assert_eq!(err, ReplayErrorKind::BoardsNotPlayed(vec![(-1, 0)], true));
+        assert_eq!(err.to_string(), "boards (-0T1) must still be played on");
+
+        let record = notation::parse(
+            "[Board \"custom\"]\n[4k3/8/8/8/8/8/8/4K3:-0:1:w]\n[4k3/8/8/8/8/8/8/R3K3:+0:1:w]\n\n1. (-0T1)Kd1 (+0T1)Ra8 / (-0T1)Kd8 (+0T1)Kd7\n",
+        );
+        let mut game = record.unwrap().game;
+        let moveset = notation::parse_moveset(&game, "(-0T2)Kd1d2 (+0T2)Ra8a7").unwrap();
+        assert!(play_moveset(&mut game, &moveset).is_ok());
+        let moveset = notation::parse_moveset(&game, "(-0T2)Kd8e8 (+0T2)Kd7c7").unwrap();
+        match play_moveset(&mut game, &moveset) {
+            Err(ReplayErrorKind::Check(checks)) => {
+                assert_eq!(checks.iter().map(|c| c.to_string()).collect::<Vec<_>>(), vec!["Ra7 (+0T3) -> Kc7 (+0T3)"]);
+            }
+            res => panic!("The moveset wasn't rejected for a check: {:?}", res),
+        }
+    }
+}
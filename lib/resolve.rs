@@ -23,6 +23,7 @@ pub const TAKE_PRINCESS_REWARD: i32 = 8;
 pub const TAKE_QUEEN_REWARD: i32 = 10;
 pub const TAKE_UNICORN_REWARD: i32 = 2;
 pub const TAKE_DRAGON_REWARD: i32 = 2;
+pub const TAKE_COMMON_KING_REWARD: i32 = 3;
 
 pub const CHECK_QUEEN_REWARD: i32 = 8;
 pub const CHECK_PRINCESS_REWARD: i32 = 6;
@@ -63,7 +64,7 @@ impl<'a> Lore<'a> {
     ) -> Lore<'a> {
         let mut res = Lore {
             board,
-            danger: vec![0; board.pieces.len()],
+            danger: vec![0; (board.width as usize) * (board.height as usize)],
             enemies: Vec::new(),
         };
 
@@ -76,7 +77,7 @@ impl<'a> Lore<'a> {
         for b in opponent_boards {
             let probables = probable_moves(game, b, &n_virtual_boards);
             for mv in probables {
-                if mv.dst_piece.is_king() {
+                if mv.dst_piece.is_royal() {
                     res.register_enemy(&mv);
                 }
                 res.register_danger(&mv);
@@ -85,7 +86,7 @@ impl<'a> Lore<'a> {
 
         let probables = probable_moves(game, &noop_board, &n_virtual_boards);
         for mv in probables {
-            if mv.dst_piece.is_king() {
+            if mv.dst_piece.is_royal() {
                 res.register_enemy(&mv);
             }
             res.register_danger(&mv);
@@ -133,11 +134,12 @@ pub fn score_moves<'a>(
                     get_board(game, virtual_boards, (mv.dst.0, mv.dst.1)).unwrap(),
                 )
             {
-                if if info.active_player {
-                    info.max_timeline >= -info.min_timeline + 1
+                // `info` already contains the new timeline
+                if !info.is_timeline_active(if info.active_player {
+                    info.max_timeline
                 } else {
-                    info.max_timeline <= -info.min_timeline - 1
-                } {
+                    info.min_timeline
+                }) {
                     score += JUMP_INACTIVE_COST;
                 } else {
                     score += JUMP_COST;
@@ -163,6 +165,8 @@ pub fn score_moves<'a>(
                 score += TAKE_BISHOP_REWARD;
             } else if mv.dst_piece.is_queen() {
                 score += TAKE_QUEEN_REWARD;
+            } else if mv.dst_piece.is_common_king() {
+                score += TAKE_COMMON_KING_REWARD;
             } else if mv.dst_piece.is_unicorn() {
                 score += TAKE_UNICORN_REWARD;
             } else if mv.dst_piece.is_dragon() {
@@ -184,14 +188,14 @@ pub fn score_moves<'a>(
             );
 
             for mv in moves {
-                if mv.dst_piece.is_king() {
+                if mv.dst_piece.is_royal() {
                     if mv.src_piece.is_knight() {
                         score += CHECK_KNIGHT_REWARD;
                     } else if mv.src_piece.is_rook() {
                         score += CHECK_ROOK_REWARD;
                     } else if mv.src_piece.is_bishop() {
                         score += CHECK_BISHOP_REWARD;
-                    } else if mv.src_piece.is_queen() {
+                    } else if mv.src_piece.is_queen() || mv.src_piece.is_royal_queen() {
                         score += CHECK_QUEEN_REWARD;
                     } else if mv.src_piece.is_unicorn() {
                         score += CHECK_UNICORN_REWARD;
@@ -215,9 +219,9 @@ pub fn score_moves<'a>(
 
             for b in &boards {
                 let mut n_kings: usize = 0;
-                for (index, piece) in b.pieces.iter().enumerate() {
-                    if *piece != Piece::Blank && piece.is_white() == board.active_player() {
-                        if piece.is_king() {
+                for (index, piece) in b.pieces().enumerate() {
+                    if piece != Piece::Blank && piece.is_white() == board.active_player() {
+                        if piece.is_royal() {
                             n_kings += 1;
                             score += (lore.danger[index] as i32) * KING_DANGER_COST;
                             if n_kings > 1 {
@@ -263,10 +267,13 @@ pub const KNIGHT_VALUE: f32 = 4.5;
 pub const PRINCESS_VALUE: f32 = 8.0;
 pub const QUEEN_VALUE: f32 = 14.0;
 pub const KING_VALUE: f32 = -4.0;
+pub const COMMON_KING_VALUE: f32 = 3.0;
+pub const ROYAL_QUEEN_VALUE: f32 = 6.0;
 pub const BISHOP_VALUE: f32 = 5.0;
 pub const UNICORN_VALUE: f32 = 3.5;
 pub const DRAGON_VALUE: f32 = 3.0;
 pub const PAWN_VALUE: f32 = 0.9;
+pub const BRAWN_VALUE: f32 = 1.2;
 
 // How much it is worth to have a well-protected king
 pub const KING_PROTECTION_VALUE: f32 = 1.5;
@@ -317,7 +324,7 @@ pub fn score_moveset<'a, T: Iterator<Item = &'a Board>>(
         && is_moveset_legal(game, &merged_vboards, &info, opponent_boards)
         && all_boards_played(game, &merged_vboards, &info)
     {
-        info.present += 1;
+        info.present = find_present(game, &merged_vboards, &info);
         info.active_player = !info.active_player;
 
         let mut score: f32 = 0.0;
@@ -331,10 +338,7 @@ pub fn score_moveset<'a, T: Iterator<Item = &'a Board>>(
                 };
             }
 
-            // TODO: fix this as per the new index system
-            let board_mult: f32 = if board.l < 0 && -board.l > info.max_timeline + 1
-                || board.l > 0 && board.l > -info.min_timeline + 1
-            {
+            let board_mult: f32 = if !info.is_timeline_active(board.l) {
                 INACTIVE_BRANCH_MULTIPLIER.powf((info.max_timeline + info.min_timeline).abs() as f32 - 1.0)
             } else {
                 1.0
@@ -349,14 +353,14 @@ pub fn score_moveset<'a, T: Iterator<Item = &'a Board>>(
                 controlled_squares_b.push(false);
             }
 
-            for (index, piece) in board.pieces.iter().enumerate() {
+            for (index, piece) in board.pieces().enumerate() {
                 let x = (index % board.width as usize) as u8;
                 let y = (index / board.width as usize) as u8;
                 if piece.is_blank() {
                     continue;
                 }
                 let mult: f32 = if piece.is_white() { 1.0 } else { -1.0 };
-                if piece.is_king() {
+                if piece.is_royal() {
                     if piece.is_white() {
                         w_kings += 1;
                         if w_kings > 1 {
@@ -368,7 +372,7 @@ pub fn score_moveset<'a, T: Iterator<Item = &'a Board>>(
                             score -= MANY_KINGS_VALUE;
                         }
                     }
-                    score += KING_VALUE * mult * board_mult;
+                    score += if piece.is_royal_queen() { ROYAL_QUEEN_VALUE } else { KING_VALUE } * mult * board_mult;
                     for dx in -1..=1 {
                         for dy in -1..=1 {
                             if dx == 0 && dy == 0
@@ -410,6 +414,10 @@ pub fn score_moveset<'a, T: Iterator<Item = &'a Board>>(
                     score += DRAGON_VALUE * mult * board_mult;
                 } else if piece.is_pawn() {
                     score += PAWN_VALUE * mult * board_mult;
+                } else if piece.is_brawn() {
+                    score += BRAWN_VALUE * mult * board_mult;
+                } else if piece.is_common_king() {
+                    score += COMMON_KING_VALUE * mult * board_mult;
                 } else if piece.is_princess() {
                     score += PRINCESS_VALUE * mult * board_mult;
                 }
@@ -417,7 +425,7 @@ pub fn score_moveset<'a, T: Iterator<Item = &'a Board>>(
                 // Maybe replace with bitboard operations
                 // Or just dedupe that horror
                 if piece.is_white() {
-                    if piece.is_pawn() {
+                    if piece.is_pawn() || piece.is_brawn() {
                         set_controlled_square(&mut controlled_squares_w, index, 1, 1, board.width, board.height);
                         set_controlled_square(&mut controlled_squares_w, index, 1, -1, board.width, board.height);
                     } else if piece.is_knight() {
@@ -432,21 +440,21 @@ pub fn score_moveset<'a, T: Iterator<Item = &'a Board>>(
                         set_controlled_square(&mut controlled_squares_w, index, -1, -2, board.width, board.height);
                     }
 
-                    if piece.is_bishop() || piece.is_queen() || piece.is_princess() {
+                    if piece.is_bishop() || piece.is_queen() || piece.is_royal_queen() || piece.is_princess() {
                         set_controlled_square_slide(board, &mut controlled_squares_w, index, 1, 1, board.width, board.height, white);
                         set_controlled_square_slide(board, &mut controlled_squares_w, index, -1, 1, board.width, board.height, white);
                         set_controlled_square_slide(board, &mut controlled_squares_w, index, 1, -1, board.width, board.height, white);
                         set_controlled_square_slide(board, &mut controlled_squares_w, index, -1, -1, board.width, board.height, white);
                     }
 
-                    if piece.is_rook() || piece.is_queen() || piece.is_princess() {
+                    if piece.is_rook() || piece.is_queen() || piece.is_royal_queen() || piece.is_princess() {
                         set_controlled_square_slide(board, &mut controlled_squares_w, index, 0, 1, board.width, board.height, white);
                         set_controlled_square_slide(board, &mut controlled_squares_w, index, 0, -1, board.width, board.height, white);
                         set_controlled_square_slide(board, &mut controlled_squares_w, index, 1, 0, board.width, board.height, white);
                         set_controlled_square_slide(board, &mut controlled_squares_w, index, -1, 0, board.width, board.height, white);
                     }
                 } else if piece.is_black() {
-                    if piece.is_pawn() {
+                    if piece.is_pawn() || piece.is_brawn() {
                         set_controlled_square(&mut controlled_squares_b, index, -1, 1, board.width, board.height);
                         set_controlled_square(&mut controlled_squares_b, index, -1, -1, board.width, board.height);
                     } else if piece.is_knight() {
@@ -461,14 +469,14 @@ pub fn score_moveset<'a, T: Iterator<Item = &'a Board>>(
                         set_controlled_square(&mut controlled_squares_b, index, -1, -2, board.width, board.height);
                     }
 
-                    if piece.is_bishop() || piece.is_queen() || piece.is_princess() {
+                    if piece.is_bishop() || piece.is_queen() || piece.is_royal_queen() || piece.is_princess() {
                         set_controlled_square_slide(board, &mut controlled_squares_b, index, 1, 1, board.width, board.height, white);
                         set_controlled_square_slide(board, &mut controlled_squares_b, index, -1, 1, board.width, board.height, white);
                         set_controlled_square_slide(board, &mut controlled_squares_b, index, 1, -1, board.width, board.height, white);
                         set_controlled_square_slide(board, &mut controlled_squares_b, index, -1, -1, board.width, board.height, white);
                     }
 
-                    if piece.is_rook() || piece.is_queen() || piece.is_princess() {
+                    if piece.is_rook() || piece.is_queen() || piece.is_royal_queen() || piece.is_princess() {
                         set_controlled_square_slide(board, &mut controlled_squares_b, index, 0, 1, board.width, board.height, white);
                         set_controlled_square_slide(board, &mut controlled_squares_b, index, 0, -1, board.width, board.height, white);
                         set_controlled_square_slide(board, &mut controlled_squares_b, index, 1, 0, board.width, board.height, white);
@@ -536,7 +544,7 @@ fn set_controlled_square_slide(board: &Board, controlled_squares: &mut Vec<bool>
         && ((index / width as usize) as isize) + length * dy < height as isize
     {
         let n_index = ((index as isize) + length * (dx + (width as isize) * dy)) as usize;
-        if board.pieces[n_index].is_takable_piece(white) {
+        if board.get_unsafe((n_index % width as usize) as u8, (n_index / width as usize) as u8).is_takable_piece(white) {
             controlled_squares[n_index] = true;
         } else {
             break;
@@ -0,0 +1,202 @@
+/*
+    Compact storage of the squares of a board (see `Board::squares`).
+
+    Boards that fit within 8x8 squares, which covers every built-in variant, pack their pieces in five bitboards held within the board itself
+    (laid out like the ones of `bitboard`): the `n`-th bitboard holds the `n`-th bit of the code of each square's piece.
+    The lower four bits of a code tell the kind of piece and the fifth one its colour, and the royal pieces share a pattern of bits,
+    so the `bitboard::Occupancy` of a board is derived from its squares in a few operations instead of being stored alongside them.
+    Such a board takes no allocation, and cloning it (which `Move::generate_vboards` does for every move) is a plain copy.
+
+    Larger boards keep one byte per square, shared between the clones of a board until one of them is modified (copy-on-write).
+*/
+
+use crate::{bitboard::{self, Bitboard, Occupancy}, game::Piece};
+use std::sync::Arc;
+
+/// Number of bits in the code of a piece
+const CODE_BITS: usize = 5;
+
+/// Bit set in the codes of the black pieces
+const BLACK: u8 = 1 << 4;
+
+/// The pieces, indexed by their code; `Piece::Blank` stands in for the unused codes
+const PIECES: [Piece; 1 << CODE_BITS] = [
+    Piece::Blank,
+    Piece::PawnW,
+    Piece::KnightW,
+    Piece::BishopW,
+    Piece::RookW,
+    Piece::QueenW,
+    Piece::UnicornW,
+    Piece::DragonW,
+    Piece::KingW,
+    Piece::RoyalQueenW,
+    Piece::PrincessW,
+    Piece::BrawnW,
+    Piece::CommonKingW,
+    Piece::Blank,
+    Piece::Blank,
+    Piece::Blank,
+    Piece::Blank,
+    Piece::PawnB,
+    Piece::KnightB,
+    Piece::BishopB,
+    Piece::RookB,
+    Piece::QueenB,
+    Piece::UnicornB,
+    Piece::DragonB,
+    Piece::KingB,
+    Piece::RoyalQueenB,
+    Piece::PrincessB,
+    Piece::BrawnB,
+    Piece::CommonKingB,
+    Piece::Blank,
+    Piece::Blank,
+    Piece::Blank,
+];
+
+/// Returns the code of `piece`; the royal pieces are the only ones whose kind is `0b100x`
+fn code(piece: Piece) -> u8 {
+    match piece {
+        Piece::Blank => 0,
+        Piece::PawnW => 1,
+        Piece::KnightW => 2,
+        Piece::BishopW => 3,
+        Piece::RookW => 4,
+        Piece::QueenW => 5,
+        Piece::UnicornW => 6,
+        Piece::DragonW => 7,
+        Piece::KingW => 8,
+        Piece::RoyalQueenW => 9,
+        Piece::PrincessW => 10,
+        Piece::BrawnW => 11,
+        Piece::CommonKingW => 12,
+        Piece::PawnB => BLACK | 1,
+        Piece::KnightB => BLACK | 2,
+        Piece::BishopB => BLACK | 3,
+        Piece::RookB => BLACK | 4,
+        Piece::QueenB => BLACK | 5,
+        Piece::UnicornB => BLACK | 6,
+        Piece::DragonB => BLACK | 7,
+        Piece::KingB => BLACK | 8,
+        Piece::RoyalQueenB => BLACK | 9,
+        Piece::PrincessB => BLACK | 10,
+        Piece::BrawnB => BLACK | 11,
+        Piece::CommonKingB => BLACK | 12,
+    }
+}
+
+/// The pieces of a board
+#[derive(Debug, Clone, PartialEq)]
+pub enum Squares {
+    /// Boards that fit within 8x8 squares: one bitboard per bit of the pieces' codes
+    Packed([Bitboard; CODE_BITS]),
+    /// Larger boards: one piece per square, indexed by `x + y * width` and shared between clones
+    Shared { width: u8, pieces: Arc<[Piece]> },
+}
+
+impl Squares {
+    /// Creates the blank squares of a `width`x`height` board
+    pub fn new(width: u8, height: u8) -> Self {
+        if bitboard::fits(width, height) {
+            Squares::Packed([0; CODE_BITS])
+        } else {
+            Squares::Shared {
+                width,
+                pieces: vec![Piece::Blank; (width as usize) * (height as usize)].into(),
+            }
+        }
+    }
+
+    /// Returns the piece at `(x, y)`; the square must be on the board
+    #[inline]
+    pub fn get(&self, x: u8, y: u8) -> Piece {
+        match self {
+            Squares::Packed(planes) => {
+                let index = x as u32 + 8 * y as u32;
+                let mut code = 0;
+                for (n, plane) in planes.iter().enumerate() {
+                    code |= ((plane >> index) & 1) << n;
+                }
+                PIECES[code as usize]
+            }
+            Squares::Shared { width, pieces } => pieces[(x as usize) + (y as usize) * (*width as usize)],
+        }
+    }
+
+    /// Puts `piece` at `(x, y)`; the square must be on the board. Shared squares are copied first
+    #[inline]
+    pub fn set(&mut self, x: u8, y: u8, piece: Piece) {
+        match self {
+            Squares::Packed(planes) => {
+                let mask = bitboard::bit(x, y);
+                let code = code(piece);
+                for (n, plane) in planes.iter_mut().enumerate() {
+                    if code >> n & 1 == 1 {
+                        *plane |= mask;
+                    } else {
+                        *plane &= !mask;
+                    }
+                }
+            }
+            Squares::Shared { width, pieces } => {
+                Arc::make_mut(pieces)[(x as usize) + (y as usize) * (*width as usize)] = piece;
+            }
+        }
+    }
+
+    /// Returns the squares occupied by each player and the ones holding royal pieces; empty for the boards that don't fit within 8x8 squares
+    #[inline]
+    pub fn occupancy(&self) -> Occupancy {
+        match self {
+            Squares::Packed([p0, p1, p2, p3, black]) => {
+                let occupied = p0 | p1 | p2 | p3;
+                Occupancy {
+                    white: occupied & !black,
+                    black: *black,
+                    royal: p3 & !p2 & !p1,
+                }
+            }
+            Squares::Shared { .. } => Occupancy::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_match_the_pieces() {
+        for (code, piece) in PIECES.iter().enumerate() {
+            if *piece != Piece::Blank {
+                assert_eq!(super::code(*piece) as usize, code);
+                assert_eq!(code as u8 & BLACK != 0, piece.is_black());
+                assert_eq!(code & 0b1110 == 0b1000, piece.is_royal());
+            }
+        }
+    }
+
+    #[test]
+    fn stores_every_piece() {
+        for size in [8, 10] {
+            let mut squares = Squares::new(size, size);
+            let piece_at = |x: u8, y: u8| PIECES[(x as usize + y as usize * 3) % PIECES.len()];
+            for y in 0..size {
+                for x in 0..size {
+                    squares.set(x, y, piece_at(x, y));
+                }
+            }
+            let shared = squares.clone();
+            squares.set(3, 1, Piece::KingB);
+
+            for y in 0..size {
+                for x in 0..size {
+                    assert_eq!(shared.get(x, y), piece_at(x, y));
+                }
+            }
+            assert_eq!(squares.get(3, 1), Piece::KingB);
+            assert_ne!(squares, shared);
+        }
+    }
+}
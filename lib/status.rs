@@ -0,0 +1,178 @@
+/*
+    Game status: whether the active player can still play, has been checkmated or is stalemated, and which pieces are giving check.
+
+    Unlike the search functions of `tree`, which deem a position to be lost or drawn once they run out of movesets to look at,
+    `game_status` looks at every moveset until it finds a legal one. It is slow on complex positions, but it can be trusted.
+*/
+
+use crate::{game::*, moves::*, moveset::MovesetIter, notation::write_board_coordinates};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The active player has at least one legal moveset
+    Ongoing,
+    /// The active player has no legal moveset and is in check; holds the winner (`true` for white)
+    Checkmate(bool),
+    /// The active player has no legal moveset but isn't in check
+    Stalemate,
+}
+
+/**
+    Returns the status of `game`, for its active player.
+
+    Every move of every playable board is considered (the moves aren't pruned nor bucketed like in `legal_movesets`),
+    as a move leaving the king in check on its own may be part of a legal moveset.
+**/
+pub fn game_status(game: &Game) -> Status {
+    if has_legal_moveset(game) {
+        Status::Ongoing
+    } else if is_draw(game, &vec![], &game.info) {
+        Status::Stalemate
+    } else {
+        Status::Checkmate(!game.info.active_player)
+    }
+}
+
+/// Returns whether or not the active player of `game` has any legal moveset, by trying out all of them until one is found
+pub fn has_legal_moveset(game: &Game) -> bool {
+    let virtual_boards: Vec<&Board> = Vec::new();
+    let moves = get_own_boards(game, &virtual_boards, &game.info)
+        .into_iter()
+        .map(|board| {
+            probable_moves(game, board, &virtual_boards)
+                .into_iter()
+                .filter_map(|mv| {
+                    let (info, boards) = mv.generate_vboards(game, &game.info, &virtual_boards, &vec![])?;
+                    Some((mv, boards, info, 0))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut movesets = MovesetIter::new(game, &virtual_boards, &game.info, moves).score();
+    movesets.next().is_some()
+}
+
+/// A royal piece that can be taken by an opponent piece
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// The attacking piece
+    pub piece: Piece,
+    /// The position of the attacking piece (l, t, x, y)
+    pub src: (i32, isize, u8, u8),
+    /// The attacked royal piece
+    pub king: Piece,
+    /// The position of the attacked royal piece (l, t, x, y)
+    pub dst: (i32, isize, u8, u8),
+    /// The squares that the attack goes through, across boards, timelines included; ends with `dst`
+    pub path: Vec<(i32, isize, u8, u8)>,
+    /// Whether the game has even initial timelines, in which case the timelines are written out as `-0` and `+0`
+    pub even_initial_timelines: bool,
+}
+
+impl Check {
+    fn new(game: &Game, mv: &Move) -> Self {
+        Check {
+            piece: mv.src_piece,
+            src: mv.src,
+            king: mv.dst_piece,
+            dst: mv.dst,
+            path: attack_path(mv.src, mv.dst),
+            even_initial_timelines: game.info.even_initial_timelines,
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    /// Writes the check as `Qd3 (0T4) -> Ke8 (0T4)`, with the pieces of both sides in uppercase
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{} {} -> {}{}{} {}",
+            self.piece.as_uppercase(),
+            write_file(self.src.2),
+            self.src.3 + 1,
+            write_board_coordinates(self.src.0, self.src.1, self.even_initial_timelines),
+            self.king.as_uppercase(),
+            write_file(self.dst.2),
+            self.dst.3 + 1,
+            write_board_coordinates(self.dst.0, self.dst.1, self.even_initial_timelines),
+        )
+    }
+}
+
+/**
+    Returns the checks that the active player of `info` is in: every opponent piece that could take one of their royal pieces,
+    were they to pass on the boards that they have to play on.
+**/
+pub fn checks(game: &Game, virtual_boards: &Vec<&Board>, info: &GameInfo) -> Vec<Check> {
+    // The opponent may also move on inactive timelines
+    let opponent_boards = get_opponent_boards(game, virtual_boards, info);
+    // The active player's boards, as if they had passed
+    let own_boards = get_own_boards(game, virtual_boards, info)
+        .into_iter()
+        .filter(|b| b.is_active(info))
+        .cloned()
+        .map(|mut b| {
+            b.t += 1;
+            b
+        })
+        .collect::<Vec<_>>();
+
+    let merged_vboards = virtual_boards
+        .iter()
+        .copied()
+        .chain(own_boards.iter())
+        .collect::<Vec<_>>();
+
+    attacks(game, &merged_vboards, opponent_boards.into_iter().chain(own_boards.iter()))
+}
+
+/// Returns the royal pieces that can be taken by a piece of `boards`
+pub fn attacks<'a, T: Iterator<Item = &'a Board>>(game: &Game, virtual_boards: &Vec<&Board>, boards: T) -> Vec<Check> {
+    let mut res = Vec::new();
+    for board in boards {
+        // Castling never takes a piece
+        for mv in piece_moves(game, board, virtual_boards) {
+            if mv.dst_piece.is_royal() {
+                res.push(Check::new(game, &mv));
+            }
+        }
+    }
+    res
+}
+
+/**
+    Returns the squares between `src` and `dst`, `dst` included. The move is split in as many equal steps as possible:
+    sliding pieces go through every square on their way while leaping pieces (knights) directly land on `dst`.
+**/
+fn attack_path(src: (i32, isize, u8, u8), dst: (i32, isize, u8, u8)) -> Vec<(i32, isize, u8, u8)> {
+    // Time is counted in turns, rather than in half-turns
+    let delta = [
+        (dst.0 - src.0) as isize,
+        (dst.1 - src.1) / 2,
+        dst.2 as isize - src.2 as isize,
+        dst.3 as isize - src.3 as isize,
+    ];
+    let steps = delta.iter().fold(0, |acc, d| gcd(acc, d.abs())).max(1);
+
+    (1..=steps)
+        .map(|n| {
+            (
+                src.0 + (delta[0] / steps * n) as i32,
+                src.1 + delta[1] / steps * n * 2,
+                (src.2 as isize + delta[2] / steps * n) as u8,
+                (src.3 as isize + delta[3] / steps * n) as u8,
+            )
+        })
+        .collect()
+}
+
+fn gcd(a: isize, b: isize) -> isize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
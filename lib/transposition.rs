@@ -0,0 +1,127 @@
+/*
+    Transposition table: remembers the results of the searches of `tree`, keyed by the Zobrist hash of the positions (see `zobrist`).
+
+    Moves made on separate timelines commute, so the same position is often reached through different moveset orders;
+    the table lets the searchers reuse the score of a position instead of searching it again.
+    The best moveset of each position is kept as well: it is searched first the next time, and lets the principal variation be rebuilt on a hit.
+    It is shared between the search threads: it is split in shards, each behind its own lock, and has a fixed number of entries.
+*/
+
+use crate::moves::Move;
+use std::sync::Mutex;
+
+/// Number of independently locked parts of the table
+const N_SHARDS: usize = 64;
+
+/// What the score of an entry tells about the actual score of its position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    /// The score is the actual score
+    Exact,
+    /// The actual score is at least the score (the search was cut off by β)
+    Lower,
+    /// The actual score is at most the score (no moveset reached α)
+    Upper,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Zobrist hash of the position
+    pub hash: u64,
+    /// Depth to which the position was searched; positions without any moveset are stored with a depth of `usize::MAX`
+    pub depth: usize,
+    pub score: f32,
+    pub bound: Bound,
+    /// The best moveset found in the position, if any
+    pub best: Option<Vec<Move>>,
+}
+
+impl Entry {
+    /**
+        Builds an entry out of the result of an αβ search, `alpha` and `beta` being the bounds that the search was given.
+    **/
+    pub fn new(hash: u64, depth: usize, score: f32, alpha: f32, beta: f32, best: Option<Vec<Move>>) -> Self {
+        Entry {
+            hash,
+            depth,
+            score,
+            bound: if score <= alpha {
+                Bound::Upper
+            } else if score >= beta {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            },
+            best,
+        }
+    }
+
+    /// Builds the entry of a position without any moveset; its score is final
+    pub fn terminal(hash: u64, score: f32) -> Self {
+        Entry {
+            hash,
+            depth: usize::MAX,
+            score,
+            bound: Bound::Exact,
+            best: None,
+        }
+    }
+
+    /// Returns whether or not the position is known to have no moveset (checkmate or draw)
+    pub fn is_terminal(&self) -> bool {
+        self.depth == usize::MAX
+    }
+}
+
+/// A thread-safe, size-bounded transposition table
+#[derive(Debug)]
+pub struct TranspositionTable {
+    shards: Vec<Mutex<Vec<Option<Entry>>>>,
+    shard_size: usize,
+}
+
+impl TranspositionTable {
+    /// Creates a new table, able to hold (at least) `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        let shard_size = capacity.div_ceil(N_SHARDS);
+        TranspositionTable {
+            shards: (0..N_SHARDS).map(|_| Mutex::new(vec![None; shard_size.max(1)])).collect(),
+            shard_size: shard_size.max(1),
+        }
+    }
+
+    /// Returns the entry of the position with the hash `hash`, if it is known
+    pub fn get(&self, hash: u64) -> Option<Entry> {
+        let (shard, index) = self.slot(hash);
+        let shard = self.shards[shard].lock().expect("Couldn't lock the transposition table");
+        shard[index].as_ref().filter(|entry| entry.hash == hash).cloned()
+    }
+
+    /// Stores `entry`; it replaces the entry in its slot unless that one was searched deeper
+    pub fn insert(&self, entry: Entry) {
+        let (shard, index) = self.slot(entry.hash);
+        let mut shard = self.shards[shard].lock().expect("Couldn't lock the transposition table");
+        let replace = match &shard[index] {
+            Some(previous) => previous.hash != entry.hash || previous.depth <= entry.depth,
+            None => true,
+        };
+        if replace {
+            shard[index] = Some(entry);
+        }
+    }
+
+    /// Removes every entry
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            let mut shard = shard.lock().expect("Couldn't lock the transposition table");
+            for entry in shard.iter_mut() {
+                *entry = None;
+            }
+        }
+    }
+
+    #[inline]
+    fn slot(&self, hash: u64) -> (usize, usize) {
+        ((hash % N_SHARDS as u64) as usize, ((hash / N_SHARDS as u64) % self.shard_size as u64) as usize)
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{game::*, moves::*};
+use crate::{game::*, moves::*, resolve::score_moveset, transposition::*, zobrist::position_hash};
 use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::rc::Rc;
@@ -9,7 +9,7 @@ use scoped_threadpool::Pool;
 
 // Tree search algorithms
 
-type Node = (Vec<Move>, Vec<Board>, GameInfo, f32);
+pub type Node = (Vec<Move>, Vec<Board>, GameInfo, f32);
 
 pub mod dfs {
     use super::*;
@@ -21,6 +21,7 @@ pub mod dfs {
         - `bucket_size` correspond to the number of movesets to score and sort; ignored if `<= max_bf`
         - `max_bf` corresponds to the maximum number of movesets (branching factor, or `b`) to consider per tree node; note that αβ-pruning has a time complexity of `O(b^(d/2))`
         - `n_threads` is the number of threads to run concurrently; they will work on different starting moves to recursively rate them
+        - `tt` is the transposition table shared by the threads; it may be reused between searches
     **/
     pub fn dfs<'a>(
         game: &'a Game,
@@ -29,6 +30,7 @@ pub mod dfs {
         bucket_size: usize,
         max_bf: usize,
         n_threads: u32,
+        tt: &TranspositionTable,
     ) -> Option<(Node, f32)> {
         let virtual_boards: Vec<&Board> = Vec::new();
         let initial_iter = legal_movesets(&game, &game.info, &virtual_boards, 0, 0).take(max_bf);
@@ -78,6 +80,7 @@ pub mod dfs {
                             max_ms,
                             bucket_size,
                             max_bf,
+                            tt,
                         );
                         if let Some(best_branch) = best_branch {
                             let mut res: String = format!("1. {:?} -> {}\n", node.0, new_value);
@@ -134,7 +137,7 @@ pub mod dfs {
     }
 
     /// Recursive bit of `dfs(...)`, see the documentation about `dfs` for more information!
-    fn dfs_rec(
+    pub(super) fn dfs_rec(
         game: &Game,
         virtual_boards: &Vec<&Board>,
         node: Node,
@@ -145,6 +148,7 @@ pub mod dfs {
         max_ms: usize,
         bucket_size: usize,
         max_bf: usize,
+        tt: &TranspositionTable,
     ) -> (Option<Vec<Node>>, f32) {
         // TODO: merge white's and black's code?
         if depth == 0 {
@@ -158,13 +162,34 @@ pub mod dfs {
                 .map(|x| *x)
                 .chain(node.1.iter())
                 .collect::<Vec<&Board>>();
-            let movesets = legal_movesets(game, &info, &merged_vboards, 0, max_ms);
 
-            if white { // White:
+            let hash = position_hash(game, &merged_vboards, &info);
+            let (alpha_orig, beta_orig) = (alpha, beta);
+            let mut tt_best = None;
+            if let Some(entry) = tt.get(hash) {
+                if entry.depth >= depth {
+                    match entry.bound {
+                        Bound::Exact => {
+                            let pv = tt_principal_variation(game, &merged_vboards, &info, depth, tt);
+                            return (if pv.is_empty() { None } else { Some(pv) }, entry.score);
+                        }
+                        Bound::Lower => alpha = alpha.max(entry.score),
+                        Bound::Upper => beta = beta.min(entry.score),
+                    }
+                    if alpha >= beta {
+                        return (None, entry.score);
+                    }
+                }
+                tt_best = entry.best.and_then(|moves| tt_node(game, &merged_vboards, &info, moves));
+            }
+
+            let mut movesets = legal_movesets(game, &info, &merged_vboards, 0, max_ms);
+
+            let (best_move, value, yielded_move) = if white { // White:
                 let mut value = std::f32::NEG_INFINITY;
                 let mut yielded_move = false;
                 let mut best_move: Option<Vec<Node>> = None;
-                for ms in opt_apply_bucket(bucket_size, max_bf, white, movesets) {
+                for ms in tt_first(tt_best, opt_apply_bucket(bucket_size, max_bf, white, movesets.by_ref())) {
                     if ms.0.len() > game.timelines.len() * 20 {
                         info!("Abnormally high number of dimensions: {}", ms.0.len());
                         info!("{:?}", ms.0);
@@ -181,6 +206,7 @@ pub mod dfs {
                         max_ms,
                         bucket_size,
                         max_bf,
+                        tt,
                     );
                     if n_value > value {
                         if let Some(mut best_branch) = best_branch {
@@ -206,12 +232,12 @@ pub mod dfs {
                     }
                 }
 
-                (best_move, value)
+                (best_move, value, yielded_move)
             } else { // Black:
                 let mut value = std::f32::INFINITY;
                 let mut yielded_move = false;
                 let mut best_move: Option<Vec<Node>> = None;
-                for ms in opt_apply_bucket(bucket_size, max_bf, white, movesets) {
+                for ms in tt_first(tt_best, opt_apply_bucket(bucket_size, max_bf, white, movesets.by_ref())) {
                     if ms.0.len() > game.timelines.len() * 20 {
                         info!("Abnormally high number of dimensions: {}", ms.0.len());
                         info!("{:?}", ms.0);
@@ -228,6 +254,7 @@ pub mod dfs {
                         max_ms,
                         bucket_size,
                         max_bf,
+                        tt,
                     );
                     if n_value < value {
                         if let Some(mut best_branch) = best_branch {
@@ -253,8 +280,17 @@ pub mod dfs {
                     }
                 }
 
-                (best_move, value)
-            }
+                (best_move, value, yielded_move)
+            };
+
+            // Without any moveset, the position is only known to be a checkmate or a draw if `max_ms` didn't cut the search short
+            tt.insert(if !yielded_move && movesets.exhaustive() {
+                Entry::terminal(hash, value)
+            } else {
+                Entry::new(hash, depth, value, alpha_orig, beta_orig, best_move.as_ref().map(|pv| pv[0].0.clone()))
+            });
+
+            (best_move, value)
         }
     }
 }
@@ -282,6 +318,8 @@ pub mod bfs {
         - `tolerance` is the maximum score difference from the best scoring node that there can be for a branch to not be pruned. If `0`, only the best scoring branches will be kept; they might turn out to not score as well deeper down the tree.
         - `tolerance_mult` is the multiplier for that score difference that will be applied to it should there be more than one consecutive pruning step; it must be lower than 1 (or else this algorithm will loop forever).
         - The `pool_size` option can also be increased to reduce the number of times that the pruning has to be ran. Doing so will, however, increase the memory usage of the program.
+
+        The transposition table `tt` is used to skip the positions known to have no moveset and to score new nodes with the results of earlier, deeper searches.
     **/
     pub fn bfs<'a>(
         game: &'a Game,
@@ -294,6 +332,7 @@ pub mod bfs {
         tolerance_mult: f32,
         n_threads: u32,
         max_duration: Duration,
+        tt: &TranspositionTable,
     ) -> Option<(Node, f32)> {
         let mut pool = Pool::new(n_threads);
         let mut res = pool.scoped(|scope| {
@@ -314,6 +353,7 @@ pub mod bfs {
                         tolerance,
                         tolerance_mult,
                         max_duration,
+                        tt,
                     );
                     results.lock().unwrap().push((initial_node, res));
                 });
@@ -425,6 +465,7 @@ pub mod bfs {
         mut tolerance: f32,
         tolerance_mult: f32,
         max_duration: Duration,
+        tt: &TranspositionTable,
     ) -> f32 {
         let mut pool: VecDeque<BFSBranch> = VecDeque::with_capacity(pool_size * 2);
         let initial_tree = Rc::new(RefCell::new(BFSTree {
@@ -466,9 +507,15 @@ pub mod bfs {
                         continue;
                     }
                     let virtual_boards = branch.boards.iter().collect::<Vec<_>>();
-                    let mut movesets = legal_movesets(game, &branch.info, &virtual_boards, 0, max_ms)
-                        .take(bucket_size)
-                        .collect::<Vec<_>>();
+                    let hash = position_hash(game, &virtual_boards, &branch.info);
+                    let terminal = tt.get(hash).filter(|entry| entry.is_terminal());
+                    let (mut movesets, exhaustive) = if terminal.is_some() {
+                        (Vec::new(), true)
+                    } else {
+                        let mut iter = legal_movesets(game, &branch.info, &virtual_boards, 0, max_ms);
+                        let movesets = iter.by_ref().take(bucket_size).collect::<Vec<_>>();
+                        (movesets, iter.exhaustive())
+                    };
                     movesets.sort_by(|a, b| {
                         if branch.info.active_player {
                             b.3.partial_cmp(&a.3).unwrap()
@@ -478,25 +525,36 @@ pub mod bfs {
                     });
 
                     if movesets.len() > 0 {
-                        for node in movesets.into_iter().take(bucket_downsize) {
+                        for mut node in movesets.into_iter().take(bucket_downsize) {
                             n_nodes += 1;
                             if pool.len() < pool_size * 2 {
+                                // Prefer the exact score of an earlier search of that position over the moveset's own score
+                                let child_boards = virtual_boards.iter().copied().chain(node.1.iter()).collect::<Vec<_>>();
+                                if let Some(entry) = tt.get(position_hash(game, &child_boards, &node.2)) {
+                                    if entry.bound == Bound::Exact {
+                                        node.3 = entry.score;
+                                    }
+                                }
                                 if let Some(new_tree) = BFSTree::after(&branch.tree, node.3) {
                                     pool.push_back(BFSBranch::from((node, &branch, new_tree)));
                                 }
                             }
                         }
                     } else {
-                        if is_draw(game, &virtual_boards, &branch.info) {
+                        if let Some(entry) = terminal {
+                            branch.score = entry.score;
+                        } else if is_draw(game, &virtual_boards, &branch.info) {
                             branch.score = 0.0;
-                            branch.tree.borrow_mut().score = branch.score;
                         } else {
                             branch.score = if branch.info.active_player {
                                 std::f32::NEG_INFINITY
                             } else {
                                 std::f32::INFINITY
                             };
-                            branch.tree.borrow_mut().score = branch.score;
+                        }
+                        branch.tree.borrow_mut().score = branch.score;
+                        if exhaustive {
+                            tt.insert(Entry::terminal(hash, branch.score));
                         }
                         pool.push_back(branch);
                         if pool.len() == 1 {
@@ -631,6 +689,7 @@ pub mod iddfs {
     - `pool_size` is the desired number of tasks to have. The actual number of tasks might exceed that number and is subject to change should some lines be worse than others. Tasks will be properly scheduled among the different threads
     - `n_threads` is the number of threads to run this with
     - `max_duration` is the maximum duration that this algorithm may take; once that maximum duration is reached, the process is stopped as soon as possible and early results, if available, are returned
+    - `tt` is the transposition table shared by the DFS searches; it carries the results of a depth over to the next one
     **/
     pub fn iddfs_bfs<'a>(
         game: &'a Game,
@@ -639,6 +698,7 @@ pub mod iddfs {
         pool_size: usize,
         n_threads: u32,
         max_duration: Duration,
+        tt: &TranspositionTable,
     ) -> Option<(Node, f32)> {
         let queue_fail_threshold = 4;
         let begin = Instant::now();
@@ -728,6 +788,7 @@ pub mod iddfs {
                                 std::f32::INFINITY,
                                 begin,
                                 max_duration,
+                                tt,
                             );
                             results.lock().unwrap().push((id, res));
                         });
@@ -793,6 +854,7 @@ pub mod iddfs {
         mut beta: f32,
         begin: Instant,
         max_duration: Duration,
+        tt: &TranspositionTable,
     ) -> Option<(Vec<Node>, f32)> {
         if begin.elapsed() >= max_duration {
             return None;
@@ -804,9 +866,32 @@ pub mod iddfs {
                 .map(|x| *x)
                 .chain(node.1.iter())
                 .collect::<Vec<&Board>>();
+
+            let hash = position_hash(game, &merged_vboards, &node.2);
+            let (alpha_orig, beta_orig) = (alpha, beta);
+            let mut tt_best = None;
+            if let Some(entry) = tt.get(hash) {
+                if entry.depth >= depth {
+                    match entry.bound {
+                        Bound::Exact => {
+                            let mut pv = tt_principal_variation(game, &merged_vboards, &node.2, depth, tt);
+                            pv.insert(0, node);
+                            return Some((pv, entry.score));
+                        }
+                        Bound::Lower => alpha = alpha.max(entry.score),
+                        Bound::Upper => beta = beta.min(entry.score),
+                    }
+                    if alpha >= beta {
+                        return Some((vec![node], entry.score));
+                    }
+                }
+                tt_best = entry.best.and_then(|moves| tt_node(game, &merged_vboards, &node.2, moves));
+            }
+
             let mut best = (vec![], if node.2.active_player {std::f32::NEG_INFINITY} else {std::f32::INFINITY});
+            let mut movesets = legal_movesets(game, &node.2, &merged_vboards, 0, max_ms);
             // Loop over the child nodes
-            for moveset in legal_movesets(game, &node.2, &merged_vboards, 0, max_ms).take(bucket_size) {
+            for moveset in tt_first(tt_best, movesets.by_ref().take(bucket_size)) {
                 let res = iddfs_bfs_sub(
                     game,
                     &merged_vboards,
@@ -817,7 +902,8 @@ pub mod iddfs {
                     alpha,
                     beta,
                     begin,
-                    max_duration
+                    max_duration,
+                    tt,
                 );
 
                 if let None = res {
@@ -844,15 +930,24 @@ pub mod iddfs {
             }
 
             if best.0.len() != 0 {
+                tt.insert(Entry::new(hash, depth, best.1, alpha_orig, beta_orig, Some(best.0[0].0.clone())));
                 let mut v = vec![node];
                 v.append(&mut best.0);
                 Some((v, best.1))
             } else {
-                if is_draw(game, &merged_vboards, &node.2) {
-                    Some((vec![node.clone()], 0.0))
+                let score = if is_draw(game, &merged_vboards, &node.2) {
+                    0.0
+                } else if node.2.active_player {
+                    std::f32::NEG_INFINITY
                 } else {
-                    Some((vec![node.clone()], if node.2.active_player {std::f32::NEG_INFINITY} else {std::f32::INFINITY}))
+                    std::f32::INFINITY
+                };
+                if movesets.exhaustive() {
+                    tt.insert(Entry::terminal(hash, score));
+                } else {
+                    tt.insert(Entry::new(hash, depth, score, alpha_orig, beta_orig, None));
                 }
+                Some((vec![node.clone()], score))
             }
         }
     }
@@ -916,6 +1011,47 @@ pub mod iddfs {
     }
 }
 
+/// Rebuilds the node reached by playing `moves`, a moveset stored in the transposition table, from the position `(virtual_boards, info)`
+fn tt_node(game: &Game, virtual_boards: &Vec<&Board>, info: &GameInfo, moves: Vec<Move>) -> Option<Node> {
+    // The moveset is checked again, in case another position with the same hash stored it
+    score_moveset(game, virtual_boards, info, get_opponent_boards(game, virtual_boards, info).into_iter(), moves)
+}
+
+/// Yields `best`, the best moveset stored in the transposition table, before the movesets of `iter`, which then leaves it out
+fn tt_first<'a, T: Iterator<Item = Node> + 'a>(best: Option<Node>, iter: T) -> impl Iterator<Item = Node> + 'a {
+    let best_moves = best.as_ref().map(|node| node.0.clone());
+    best.into_iter().chain(iter.filter(move |node| Some(&node.0) != best_moves.as_ref()))
+}
+
+/**
+    Rebuilds the principal variation of the position `(virtual_boards, info)` out of the transposition table, by following the best movesets of the stored positions.
+    The variation holds at most `depth` nodes, and stops at the first position without a best moveset.
+**/
+fn tt_principal_variation(game: &Game, virtual_boards: &Vec<&Board>, info: &GameInfo, depth: usize, tt: &TranspositionTable) -> Vec<Node> {
+    let mut res: Vec<Node> = Vec::with_capacity(depth);
+    let mut boards: Vec<Board> = Vec::new();
+    let mut info = *info;
+
+    while res.len() < depth {
+        let node = {
+            let merged_vboards = virtual_boards.iter().copied().chain(boards.iter()).collect::<Vec<&Board>>();
+            tt.get(position_hash(game, &merged_vboards, &info))
+                .and_then(|entry| entry.best)
+                .and_then(|moves| tt_node(game, &merged_vboards, &info, moves))
+        };
+        match node {
+            Some(node) => {
+                boards.extend(node.1.iter().cloned());
+                info = node.2;
+                res.push(node);
+            }
+            None => break,
+        }
+    }
+
+    res
+}
+
 /// Optionally applies the `bucket_size` option to the legal movesets iterator; `bucket_size` will be ignored if it is less than or equal to `max_bf`
 fn opt_apply_bucket<'a, T: Iterator<Item = Node> + 'a>(
     bucket_size: usize,
@@ -937,3 +1073,42 @@ fn opt_apply_bucket<'a, T: Iterator<Item = Node> + 'a>(
         Box::new(iter.take(max_bf))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variants::Variant;
+
+    #[test]
+    fn principal_variation_survives_tt_hits() {
+        let game = Variant::VerySmall.game();
+        let virtual_boards: Vec<&Board> = Vec::new();
+        let root = legal_movesets(&game, &game.info, &virtual_boards, 0, 0).next().unwrap();
+        let tt = TranspositionTable::new(1 << 16);
+        let search = || {
+            let (pv, score) = dfs::dfs_rec(
+                &game,
+                &virtual_boards,
+                root.clone(),
+                2,
+                f32::NEG_INFINITY,
+                f32::INFINITY,
+                root.2.active_player,
+                0,
+                0,
+                64,
+                &tt,
+            );
+            (pv.unwrap().into_iter().map(|node| node.0).collect::<Vec<_>>(), score)
+        };
+
+        let (pv, score) = search();
+        assert_eq!(pv.len(), 2);
+        // The second search is answered by the entry of the first one
+        let merged_vboards = root.1.iter().collect::<Vec<_>>();
+        let entry = tt.get(position_hash(&game, &merged_vboards, &root.2)).unwrap();
+        assert_eq!(entry.bound, Bound::Exact);
+        assert_eq!(entry.best.as_ref(), Some(&pv[0]));
+        assert_eq!(search(), (pv, score));
+    }
+}
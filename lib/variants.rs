@@ -0,0 +1,172 @@
+/*
+    Starting positions of the official variants.
+
+    Other positions can be created by writing out their boards in 5DFEN (see `notation::parse_fen`) and passing them to `from_boards`.
+*/
+
+use crate::{game::*, notation::{fen_even_timelines, parse_fen}, replay};
+
+/// The official variants, as they are selected with the `Board` header of a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Standard,
+    DefendedPawn,
+    HalfReflected,
+    Princess,
+    ReversedRoyalty,
+    TurnZero,
+    TwoTimelines,
+    KnightsVsBishops,
+    SimpleSet,
+    Focused,
+    Small,
+    VerySmall,
+    TimelineInvasion,
+}
+
+/// Every variant of `Variant`
+pub const VARIANTS: [Variant; 13] = [
+    Variant::Standard,
+    Variant::DefendedPawn,
+    Variant::HalfReflected,
+    Variant::Princess,
+    Variant::ReversedRoyalty,
+    Variant::TurnZero,
+    Variant::TwoTimelines,
+    Variant::KnightsVsBishops,
+    Variant::SimpleSet,
+    Variant::Focused,
+    Variant::Small,
+    Variant::VerySmall,
+    Variant::TimelineInvasion,
+];
+
+impl Variant {
+    /// Returns the name of the variant, as displayed in-game
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variant::Standard => "Standard",
+            Variant::DefendedPawn => "Standard - Defended Pawn",
+            Variant::HalfReflected => "Standard - Half Reflected",
+            Variant::Princess => "Standard - Princess",
+            Variant::ReversedRoyalty => "Standard - Reversed Royalty",
+            Variant::TurnZero => "Standard - Turn Zero",
+            Variant::TwoTimelines => "Standard - Two Timelines",
+            Variant::KnightsVsBishops => "Simple - Knights vs Bishops",
+            Variant::SimpleSet => "Simple - Simple Set",
+            Variant::Focused => "Focused",
+            Variant::Small => "Small",
+            Variant::VerySmall => "Very Small",
+            Variant::TimelineInvasion => "Misc - Timeline Invasion",
+        }
+    }
+
+    /// Finds a variant by its name (case-insensitive); the group of the variant (`Standard - `, `Simple - `, `Misc - `) may be omitted
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        VARIANTS.iter().copied().find(|variant| {
+            let full = variant.name();
+            full.eq_ignore_ascii_case(name)
+                || full
+                    .split_once(" - ")
+                    .map(|(_group, short)| short.eq_ignore_ascii_case(name))
+                    .unwrap_or(false)
+        })
+    }
+
+    /// Returns the starting boards of the variant, in 5DFEN
+    pub fn boards(&self) -> &'static [&'static str] {
+        match self {
+            Variant::Standard => &["r*nbqk*bnr*/p*p*p*p*p*p*p*p*/8/8/8/8/P*P*P*P*P*P*P*P*/R*NBQK*BNR*:0:1:w"],
+            Variant::DefendedPawn => &["r*qbnk*bnr*/p*p*p*p*p*p*p*p*/8/8/8/8/P*P*P*P*P*P*P*P*/R*QBNK*BNR*:0:1:w"],
+            Variant::HalfReflected => &["r*nbk*qbnr*/p*p*p*p*p*p*p*p*/8/8/8/8/P*P*P*P*P*P*P*P*/R*NBQK*BNR*:0:1:w"],
+            Variant::Princess => &["r*nbsk*bnr*/p*p*p*p*p*p*p*p*/8/8/8/8/P*P*P*P*P*P*P*P*/R*NBSK*BNR*:0:1:w"],
+            Variant::ReversedRoyalty => &["r*nbyc*bnr*/p*p*p*p*p*p*p*p*/8/8/8/8/P*P*P*P*P*P*P*P*/R*NBYC*BNR*:0:1:w"],
+            Variant::TurnZero => &[
+                "r*nbqk*bnr*/p*p*p*p*p*p*p*p*/8/8/8/8/P*P*P*P*P*P*P*P*/R*NBQK*BNR*:0:0:b",
+                "r*nbqk*bnr*/p*p*p*p*p*p*p*p*/8/8/8/8/P*P*P*P*P*P*P*P*/R*NBQK*BNR*:0:1:w",
+            ],
+            Variant::TwoTimelines => &[
+                "r*nbqk*bnr*/p*p*p*p*p*p*p*p*/8/8/8/8/P*P*P*P*P*P*P*P*/R*NBQK*BNR*:-0:1:w",
+                "r*nbqk*bnr*/p*p*p*p*p*p*p*p*/8/8/8/8/P*P*P*P*P*P*P*P*/R*NBQK*BNR*:+0:1:w",
+            ],
+            Variant::KnightsVsBishops => &["r*nnqk*nnr*/p*p*p*p*p*p*p*p*/8/8/8/8/P*P*P*P*P*P*P*P*/R*BBQK*BBR*:0:1:w"],
+            Variant::SimpleSet => &["r*3k*2r*/p*p*p*p*p*p*p*p*/8/8/8/8/P*P*P*P*P*P*P*P*/R*3K*2R*:0:1:w"],
+            Variant::Focused => &["r*2qk*2r*/p*p*p*p*p*p*p*p*/8/8/8/8/P*P*P*P*P*P*P*P*/R*2QK*2R*:0:1:w"],
+            Variant::Small => &["nbrqk/p*p*p*p*p*/5/P*P*P*P*P*/KQRBN:0:1:w"],
+            Variant::VerySmall => &["nbrk/3p*/P*3/KRBN:0:1:w"],
+            // Each player's army starts on its own timeline, facing the opponent's lone king
+            Variant::TimelineInvasion => &[
+                "4k3/8/8/8/8/8/P*P*P*P*P*P*P*P*/R*NBQK*BNR*:-0:1:w",
+                "r*nbqk*bnr*/p*p*p*p*p*p*p*p*/8/8/8/8/8/4K3:+0:1:w",
+            ],
+        }
+    }
+
+    /// Creates a new `Game` instance, set up with the starting position of the variant
+    pub fn game(&self) -> Game {
+        let even_initial_timelines = fen_even_timelines(self.boards().iter().copied());
+        let boards = self
+            .boards()
+            .iter()
+            .map(|raw| parse_fen(raw, even_initial_timelines).expect("Invalid built-in board"))
+            .collect::<Vec<_>>();
+        from_boards(&boards, even_initial_timelines)
+    }
+}
+
+impl Game {
+    /// Creates a new `Game` instance, set up with the starting position of `variant`
+    pub fn from_variant(variant: Variant) -> Self {
+        variant.game()
+    }
+}
+
+/**
+    Builds a game out of its initial boards: fills the timelines, sets the `GameInfo` up and populates the castling rights.
+    Boards belonging to the same timeline must be given in order; every board must have the same size.
+**/
+pub fn from_boards(boards: &[Board], even_initial_timelines: bool) -> Game {
+    let (width, height) = boards.first().map(|b| (b.width, b.height)).unwrap_or((8, 8));
+    let mut game = Game::new(width, height);
+    game.info.even_initial_timelines = even_initial_timelines;
+
+    for board in boards {
+        match game.get_timeline_mut(board.l) {
+            Some(tl) => tl.states.push(board.clone()),
+            None => {
+                let mut tl = Timeline::new(board.l, width, height, board.t, None);
+                tl.states.push(board.clone());
+                game.timelines.insert(board.l, tl);
+            }
+        }
+    }
+
+    game.info.min_timeline = game.timelines.keys().copied().min().unwrap_or(0);
+    game.info.max_timeline = game.timelines.keys().copied().max().unwrap_or(0);
+    game.info.present = replay::present(&game);
+    game.info.active_player = game.info.present % 2 == 0;
+    populate_castling_rights(&mut game);
+    game.rehash();
+
+    game
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+
+    #[test]
+    fn records_start_from_every_variant() {
+        for variant in VARIANTS.iter() {
+            let short = variant.name().split_once(" - ").map(|(_group, short)| short).unwrap_or(variant.name());
+            assert_eq!(Variant::from_name(&short.to_lowercase()), Some(*variant));
+
+            let record = notation::parse(&format!("[Board \"{}\"]\n", variant.name())).unwrap();
+            let game = variant.game();
+            assert_eq!(record.game.hash, game.hash);
+            assert_eq!(game.info.even_initial_timelines, matches!(variant, Variant::TwoTimelines | Variant::TimelineInvasion));
+        }
+    }
+}
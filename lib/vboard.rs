@@ -25,6 +25,9 @@ pub trait VirtualBoardset<'a> {
     /// Appends a set of boards to a boardset.
     /// It is expected that `∀l ∀t, (a.get_board(l, t) is Some) => (push(a, ...).get_board(l, t) is Some)` (ie. `a ⊂ push(a, ...)`)
     fn push(&'a self, boards: Vec<Board>) -> Self;
+
+    /// Returns the Zobrist hash of the boards of the game and of the virtual boards; XOR it with `zobrist::info_key` to identify a position
+    fn hash(&'a self) -> u64;
 }
 
 pub fn empty<'b>(game: &'b Game) -> EmptyVirtualBoardset<'b> {
@@ -35,6 +38,7 @@ pub fn empty<'b>(game: &'b Game) -> EmptyVirtualBoardset<'b> {
 pub struct SimpleVirtualBoardset<'a> {
     pub game: &'a Game,
     pub virtual_boards: HashMap<(i32, isize), Board>,
+    pub hash: u64,
 }
 
 impl<'a> VirtualBoardset<'a> for SimpleVirtualBoardset<'a> {
@@ -42,10 +46,11 @@ impl<'a> VirtualBoardset<'a> for SimpleVirtualBoardset<'a> {
         let mut res = Self {
             game,
             virtual_boards: HashMap::with_capacity(boards.len()),
+            hash: game.hash,
         };
 
         for board in boards.into_iter() {
-            res.virtual_boards.insert((board.l, board.t), board);
+            res.insert(board);
         }
 
         res
@@ -60,12 +65,16 @@ impl<'a> VirtualBoardset<'a> for SimpleVirtualBoardset<'a> {
     fn push(&'a self, boards: Vec<Board>) -> Self {
         let mut res = self.clone();
         for board in boards.into_iter() {
-            res.virtual_boards.insert((board.l, board.t), board);
+            res.insert(board);
         }
 
         res
     }
 
+    fn hash(&'a self) -> u64 {
+        self.hash
+    }
+
     fn game(&'a self) -> &'a Game {
         self.game
     }
@@ -75,11 +84,22 @@ impl<'a> VirtualBoardset<'a> for SimpleVirtualBoardset<'a> {
     }
 }
 
+impl<'a> SimpleVirtualBoardset<'a> {
+    /// Inserts a board, replacing the virtual board at the same `(l, t)` coordinates, if any
+    fn insert(&mut self, board: Board) {
+        self.hash ^= board.zobrist();
+        if let Some(previous) = self.virtual_boards.insert((board.l, board.t), board) {
+            self.hash ^= previous.zobrist();
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RecursiveVirtualBoardset<'a> {
     pub game: &'a Game,
     pub virtual_boards: HashMap<(i32, isize), Board>,
     pub parent: Option<&'a RecursiveVirtualBoardset<'a>>,
+    pub hash: u64,
 }
 
 impl<'a> VirtualBoardset<'a> for RecursiveVirtualBoardset<'a> {
@@ -88,9 +108,11 @@ impl<'a> VirtualBoardset<'a> for RecursiveVirtualBoardset<'a> {
             game,
             virtual_boards: HashMap::with_capacity(boards.len()),
             parent: None,
+            hash: game.hash,
         };
 
         for board in boards.into_iter() {
+            res.hash ^= board.zobrist();
             res.virtual_boards.insert((board.l, board.t), board);
         }
 
@@ -108,15 +130,21 @@ impl<'a> VirtualBoardset<'a> for RecursiveVirtualBoardset<'a> {
             game: self.game,
             virtual_boards: HashMap::with_capacity(boards.len()),
             parent: Some(self),
+            hash: self.hash,
         };
 
         for board in boards.into_iter() {
+            res.hash ^= board.zobrist();
             res.virtual_boards.insert((board.l, board.t), board);
         }
 
         res
     }
 
+    fn hash(&'a self) -> u64 {
+        self.hash
+    }
+
     fn game(&'a self) -> &'a Game {
         self.game
     }
@@ -174,6 +202,10 @@ impl<'a> VirtualBoardset<'a> for EmptyVirtualBoardset<'a> {
         Self(self.0)
     }
 
+    fn hash(&'a self) -> u64 {
+        self.0.hash
+    }
+
     fn game(&'a self) -> &'a Game {
         self.0
     }
@@ -213,6 +245,7 @@ impl<'a> From<SimpleVirtualBoardset<'a>> for RecursiveVirtualBoardset<'a> {
             game: simple.game,
             virtual_boards: simple.virtual_boards,
             parent: None,
+            hash: simple.hash,
         }
     }
 }
@@ -223,6 +256,7 @@ impl<'a> From<&'a SimpleVirtualBoardset<'a>> for RecursiveVirtualBoardset<'a> {
             game: simple.game,
             virtual_boards: simple.virtual_boards.clone(),
             parent: None,
+            hash: simple.hash,
         }
     }
 }
@@ -0,0 +1,59 @@
+/*
+    Zobrist hashing of positions.
+
+    Every `(l, t, x, y, piece)` tuple has a pseudo-random 64-bit key, and a position's hash is made by XOR-ing together the keys
+    of its pieces and a key derived from its `GameInfo`. As the multiverse has no fixed size, the keys are derived from the coordinates
+    on the fly instead of being read from a table:
+
+    - `Board::hash` is the XOR of the `square_key`s of the pieces of a board; it is kept up to date by `Board::set`
+    - `board_key` combines it with the board's `(l, t)` coordinates and castling rights
+    - `Game::hash` is the XOR of the `board_key`s of every board of the game; it is kept up to date by `Game::commit_moves` and `Game::undo`
+    - the virtual boardsets of `vboard` add the `board_key`s of their virtual boards to it
+
+    Two positions with the same hash are very likely, but not guaranteed, to be the same.
+*/
+
+use crate::game::*;
+
+/// splitmix64's finalizer: spreads the bits of `z` over the whole output
+#[inline]
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Returns the key of `piece` on the `(x, y)` square of a board; blank squares have a key of 0
+#[inline]
+pub fn square_key(x: u8, y: u8, piece: Piece) -> u64 {
+    if piece.is_blank() {
+        0
+    } else {
+        mix((usize::from(piece) as u64) << 16 | (x as u64) << 8 | y as u64)
+    }
+}
+
+/// Returns the key of `board`, which depends on its pieces, its `(l, t)` coordinates and its castling rights
+pub fn board_key(board: &Board) -> u64 {
+    let castling = (board.castle_w.0 as u64)
+        | (board.castle_w.1 as u64) << 1
+        | (board.castle_b.0 as u64) << 2
+        | (board.castle_b.1 as u64) << 3;
+    let coordinates = mix((board.l as u32 as u64) << 32 | (board.t as u32 as u64)) ^ castling;
+    mix(board.hash ^ mix(coordinates))
+}
+
+/// Returns the hash of the position made of the boards of `game`, `virtual_boards` and `info`
+pub fn position_hash(game: &Game, virtual_boards: &Vec<&Board>, info: &GameInfo) -> u64 {
+    virtual_boards.iter().fold(game.hash, |hash, b| hash ^ b.zobrist()) ^ info_key(info)
+}
+
+/// Returns the key of the `GameInfo` fields, to be XOR-ed with the keys of the boards
+pub fn info_key(info: &GameInfo) -> u64 {
+    mix(
+        (info.present as u32 as u64) << 32
+            | (info.active_player as u64) << 31
+            | (info.even_initial_timelines as u64) << 30,
+    ) ^ mix((info.min_timeline as u32 as u64) << 32 | info.max_timeline as u32 as u64)
+}
@@ -0,0 +1,154 @@
+// Command-line arguments of the `chess5dtools` executable
+
+use std::time::Duration;
+
+pub const USAGE: &str = "Usage: chess5dtools [command] [options] <path>
+
+Commands:
+  analyse <path>     display the position and the engine's best moveset (default)
+  bestmove <path>    only print the engine's best moveset, in 5dpgn, followed by its score
+  validate <path>    check that a game can be read and that all of its movesets are legal
+  convert <path>     write a game (5dpgn or JSON) back out in 5dpgn
+  perft <path>       count the legal movesets up to a given depth, with a breakdown per first moveset
+  play [path]        play against the engine, from the given game or from the standard position
+
+Search options (analyse, bestmove, play):
+  -a, --algorithm <dfs|bfs|iddfs>   search algorithm (default: iddfs)
+  -d, --depth <n>                   search depth, for dfs and perft (default: 3)
+  -j, --threads <n>                 number of threads (default: 16)
+  -t, --time <seconds>              time budget, for bfs and iddfs (default: 5)
+      --max-ms <n>                  maximum number of movesets to look at before deeming a position to be draw or checkmate (default: 10000)
+      --bucket-size <n>             number of movesets to score and sort per node (default: 64)
+      --bucket-downsize <n>         number of these movesets that bfs keeps per node (default: a tenth of the bucket size)
+
+Other options:
+  -o, --output <path>               write the output of convert to a file instead of the standard output
+  -h, --help                        display this message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Dfs,
+    Bfs,
+    Iddfs,
+}
+
+/// Parameters handed to the search functions of `chess5dlib::tree`
+#[derive(Debug, Clone, Copy)]
+pub struct SearchOptions {
+    pub algorithm: Algorithm,
+    pub depth: usize,
+    pub n_threads: u32,
+    pub max_duration: Duration,
+    pub max_ms: usize,
+    pub bucket_size: usize,
+    /// Number of the sorted movesets kept per node by bfs; `None` keeps a tenth of `bucket_size`
+    pub bucket_downsize: Option<usize>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            algorithm: Algorithm::Iddfs,
+            depth: 3,
+            n_threads: 16,
+            max_duration: Duration::from_secs(5),
+            max_ms: 10000,
+            bucket_size: 64,
+            bucket_downsize: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Analyse(String, SearchOptions),
+    BestMove(String, SearchOptions),
+    Validate(String),
+    /// Input path and optional output path
+    Convert(String, Option<String>),
+    /// Path and depth
+    Perft(String, usize),
+    Play(Option<String>, SearchOptions),
+    Help,
+}
+
+/// Reads the arguments given to the executable (without the executable's name); for compatibility, `chess5dtools <path>` is read as `chess5dtools analyse <path>`
+pub fn parse_args<T: Iterator<Item = String>>(args: T) -> Result<Command, String> {
+    let mut args = args.peekable();
+    let command = match args.peek().map(|arg| arg.as_str()) {
+        Some("analyse") | Some("analyze") | Some("bestmove") | Some("validate") | Some("convert") | Some("perft") | Some("play") => {
+            args.next().unwrap()
+        }
+        _ => String::from("analyse"),
+    };
+
+    let mut options = SearchOptions::default();
+    let mut output: Option<String> = None;
+    let mut path: Option<String> = None;
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            if path.is_some() {
+                return Err(format!("Unexpected argument: {}", arg));
+            }
+            path = Some(arg);
+            continue;
+        }
+
+        // Accept both `--max-ms` and `--max_ms`, as well as `--time=5`
+        let (flag, inline_value) = match arg.find('=') {
+            Some(index) => (arg[..index].replace('_', "-"), Some(arg[index + 1..].to_string())),
+            None => (arg.replace('_', "-"), None),
+        };
+        if flag == "-h" || flag == "--help" {
+            return Ok(Command::Help);
+        }
+        let value = match inline_value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(format!("Missing value for {}", flag)),
+        };
+
+        match flag.as_str() {
+            "-a" | "--algorithm" => {
+                options.algorithm = match value.to_lowercase().as_str() {
+                    "dfs" => Algorithm::Dfs,
+                    "bfs" => Algorithm::Bfs,
+                    "iddfs" => Algorithm::Iddfs,
+                    _ => return Err(format!("Unknown algorithm: {}", value)),
+                }
+            }
+            "-d" | "--depth" => options.depth = parse_number(&flag, &value)?,
+            "-j" | "--threads" => options.n_threads = parse_number(&flag, &value)?,
+            "-t" | "--time" => {
+                let seconds: f64 = parse_number(&flag, &value)?;
+                if !seconds.is_finite() || seconds < 0.0 {
+                    return Err(format!("Invalid value for {}: {}", flag, value));
+                }
+                options.max_duration = Duration::from_secs_f64(seconds);
+            }
+            "--max-ms" => options.max_ms = parse_number(&flag, &value)?,
+            "--bucket-size" => options.bucket_size = parse_number(&flag, &value)?,
+            "--bucket-downsize" => options.bucket_downsize = Some(parse_number(&flag, &value)?),
+            "-o" | "--output" => output = Some(value),
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+
+    if options.n_threads == 0 {
+        return Err(String::from("--threads must be at least 1"));
+    }
+
+    match (command.as_str(), path) {
+        ("play", path) => Ok(Command::Play(path, options)),
+        (_, None) => Err(String::from("Missing path")),
+        ("bestmove", Some(path)) => Ok(Command::BestMove(path, options)),
+        ("validate", Some(path)) => Ok(Command::Validate(path)),
+        ("convert", Some(path)) => Ok(Command::Convert(path, output)),
+        ("perft", Some(path)) => Ok(Command::Perft(path, options.depth)),
+        (_, Some(path)) => Ok(Command::Analyse(path, options)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
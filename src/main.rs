@@ -1,34 +1,157 @@
 #[allow(unused_imports)]
-use chess5dlib::{game::*, moves::*, moveset::*, resolve::*, tree::*, parse::parse};
+use chess5dlib::{game::*, moves::*, moveset::*, resolve::*, tree::*, parse::parse, perft::perft, status::*, transposition::TranspositionTable, notation::{self, GameRecord}};
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 extern crate json;
 
-// TODO: move replay, game analysis, args
+mod cli;
+mod repl;
+
+use cli::{Algorithm, Command, SearchOptions};
+
+// Search parameters that aren't exposed as options
+const DFS_MAX_BF: usize = 256;
+const BFS_POOL_SIZE: usize = 100000;
+const BFS_INITIAL_MOVESETS: usize = 64;
+const BFS_TOLERANCE: f32 = 100.0;
+const BFS_TOLERANCE_MULT: f32 = 0.95;
+const IDDFS_POOL_SIZE: usize = 1024;
+/// Number of positions that the transposition table can hold
+const TT_SIZE: usize = 1 << 18;
 
 fn main() -> std::io::Result<()> {
     env_logger::builder()
         .format_timestamp(None)
         .init();
 
-    // This is a simple example which will take the 40 most promising movesets, sort them by their score and display the 3 best movesets
-    let path = env::args().last().unwrap();
+    let command = match cli::parse_args(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    let mut file = File::open(path)?;
-    let mut contents = String::new();
+    match command {
+        Command::Help => println!("{}", cli::USAGE),
+        Command::Analyse(path, options) => analyse(load(&path).game, &options),
+        Command::BestMove(path, options) => {
+            let game = load(&path).game;
+            match search(&game, &options) {
+                Some((best, value)) => {
+                    let moves = best.0
+                        .iter()
+                        .filter(|mv| !mv.noop)
+                        .map(|mv| notation::write_move(&game, mv))
+                        .collect::<Vec<_>>();
+                    println!("{}", moves.join(" "));
+                    println!("{}", value);
+                }
+                None => {
+                    match game_status(&game) {
+                        Status::Checkmate(_) => println!("checkmate"),
+                        Status::Stalemate => println!("draw"),
+                        Status::Ongoing => println!("no moveset found"),
+                    }
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Validate(path) => {
+            let record = load(&path);
+            println!(
+                "{}: {} movesets, turn {}, {} to play",
+                path,
+                record.turns.len(),
+                notation::turn_number(record.game.info.present),
+                if record.game.info.active_player { "white" } else { "black" }
+            );
+        }
+        Command::Convert(path, output) => {
+            let written = match notation::write(&load(&path)) {
+                Ok(written) => written,
+                Err(err) => {
+                    eprintln!("Couldn't write {}: {}", path, err);
+                    std::process::exit(1);
+                }
+            };
+            match output {
+                Some(output) => std::fs::write(output, written)?,
+                None => print!("{}", written),
+            }
+        }
+        Command::Perft(path, depth) => {
+            let mut game = load(&path).game;
+            let res = perft(&mut game, depth);
+            for (moveset, count) in &res.divide {
+                let moves = moveset.iter().map(|mv| notation::write_move(&game, mv)).collect::<Vec<_>>();
+                println!("{}: {}", moves.join(" "), count);
+            }
+            println!();
+            for (ply, nodes) in res.nodes.iter().enumerate() {
+                println!("Depth {}: {}", ply + 1, nodes);
+            }
+        }
+        Command::Play(path, options) => {
+            let record = match path {
+                Some(path) => load(&path),
+                None => notation::parse("[Board \"Standard\"]\n").expect("Couldn't set up the standard position"),
+            };
+            repl::run(record, options)?;
+        }
+    }
 
-    file.read_to_string(&mut contents)?;
+    Ok(())
+}
 
-    let mut game = parse(&contents).expect("Couldn't parse JSON");
+/// Runs the search algorithm selected in `options` on `game`
+pub fn search(game: &Game, options: &SearchOptions) -> Option<(Node, f32)> {
+    let tt = TranspositionTable::new(TT_SIZE);
+    match options.algorithm {
+        Algorithm::Dfs => dfs::dfs(
+            game,
+            options.depth,
+            options.max_ms,
+            options.bucket_size,
+            DFS_MAX_BF,
+            options.n_threads,
+            &tt,
+        ),
+        Algorithm::Bfs => bfs::bfs(
+            game,
+            options.max_ms,
+            options.bucket_size,
+            options.bucket_downsize.unwrap_or((options.bucket_size / 10).max(1)),
+            BFS_POOL_SIZE,
+            BFS_INITIAL_MOVESETS,
+            BFS_TOLERANCE,
+            BFS_TOLERANCE_MULT,
+            options.n_threads,
+            options.max_duration,
+            &tt,
+        ),
+        Algorithm::Iddfs => iddfs::iddfs_bfs(
+            game,
+            options.max_ms,
+            options.bucket_size,
+            IDDFS_POOL_SIZE,
+            options.n_threads,
+            options.max_duration,
+            &tt,
+        ),
+    }
+}
 
+/// Displays the boards to play on and the best moveset found
+fn analyse(mut game: Game, options: &SearchOptions) {
     let virtual_boards: Vec<&Board> = vec![];
 
     println!("Boards:");
     let own_boards = get_own_boards(&game, &virtual_boards, &game.info);
     for b in own_boards {
         println!("{}", b);
-        println!("({}T{}{}) - {}/{}\n", write_timeline(b.l, game.info.even_initial_timelines), b.t / 2 + 1, if b.active_player() {"w"} else {"b"}, b.l, b.t);
+        println!("{}{} - {}/{}\n", notation::write_board(b, game.info.even_initial_timelines), if b.active_player() {"w"} else {"b"}, b.l, b.t);
         println!("");
     }
 
@@ -48,7 +171,7 @@ fn main() -> std::io::Result<()> {
 
     println!(
         "Turn {}, {} to play: (raw present = {})",
-        ((game.info.present) / 2) + 1,
+        notation::turn_number(game.info.present),
         if game.info.active_player {
             "white"
         } else {
@@ -70,28 +193,21 @@ fn main() -> std::io::Result<()> {
     //     16,
     //     std::time::Duration::new(60 * 4, 0),
     // );
-    let best_move = iddfs::iddfs_bfs(
-        &game,
-        10000,
-        64,
-        1024,
-        16,
-        std::time::Duration::new(5, 0),
-    );
+    let best_move = search(&game, options);
     if let Some((best, value)) = best_move {
         println!("Best move:");
         println!("{:?}: {}", best.0, value);
         for b in &best.1 {
             println!("{}", b);
-            println!("({}T{}{})\n", write_timeline(b.l, game.info.even_initial_timelines), b.t / 2 + 1, if b.active_player() {"w"} else {"b"});
+            println!("{}{}\n", notation::write_board(b, game.info.even_initial_timelines), if b.active_player() {"w"} else {"b"});
         }
-        game.commit_moves(best.1);
+        game.commit_moves(best.1).expect("Couldn't commit the best moveset");
         game.info = best.2;
     } else {
-        if is_draw(&game, &virtual_boards, &game.info) {
-            println!("Draw!");
-        } else {
-            println!("Checkmate! {} wins!", if game.info.active_player {"Black"} else {"White"});
+        match game_status(&game) {
+            Status::Checkmate(white) => println!("Checkmate! {} wins!", if white {"White"} else {"Black"}),
+            Status::Stalemate => println!("Draw!"),
+            Status::Ongoing => println!("No moveset was found within the search limits"),
         }
         // break;
     }
@@ -116,5 +232,29 @@ fn main() -> std::io::Result<()> {
     //     }
     // }
 
-    Ok(())
+}
+
+/// Reads a game, either written in 5dpgn or in JSON; exits if it can't be read
+fn load(path: &str) -> GameRecord {
+    match read_record(path) {
+        Ok(record) => record,
+        Err(err) => {
+            eprintln!("Couldn't read {}: {}", path, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads a game, either written in 5dpgn or in JSON
+fn read_record(path: &str) -> Result<GameRecord, String> {
+    let mut file = File::open(path).map_err(|err| err.to_string())?;
+    let mut contents = String::new();
+
+    file.read_to_string(&mut contents).map_err(|err| err.to_string())?;
+
+    if contents.trim_start().starts_with('{') {
+        parse(&contents).map(GameRecord::from_game).map_err(|err| err.to_string())
+    } else {
+        notation::parse(&contents).map_err(|err| err.to_string())
+    }
 }
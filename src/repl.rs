@@ -0,0 +1,195 @@
+// Interactive mode: play against the engine from the terminal
+
+use crate::{cli::SearchOptions, search};
+use chess5dlib::{game::*, moves::*, notation::{self, GameRecord}, replay, status::*};
+use std::io::{self, prelude::*};
+
+const HELP: &str = "Commands:
+  <moves>         play a moveset (for instance `(0T1)e3` or `(0T5)Nf3>>(0T3)g3 (1T4)Nh4`); the engine then answers
+  go              let the engine play the current moveset
+  boards          display the active boards
+  moves <board>   list the moves that can be made on a board of the active player (for instance `moves (0T3)`)
+  eval            search the position and display the engine's preferred moveset and its score
+  undo            take back your last moveset, along with the engine's answer
+  save <path>     write the game to a file, in 5dpgn
+  help            display this message
+  quit            exit";
+
+/// Runs the read-eval-print loop on `record` until `quit` is entered or the input ends; the engine searches with `options`
+pub fn run(mut record: GameRecord, options: SearchOptions) -> io::Result<()> {
+    let stdin = io::stdin();
+    // The player who typed the last moveset, whose turn it is again after an `undo`
+    let mut user: Option<bool> = None;
+    println!("Type `help` for a list of commands.");
+    print_boards(&record.game);
+
+    loop {
+        print!("{} > ", if record.game.info.active_player { "white" } else { "black" });
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim();
+        let (command, argument) = match line.find(char::is_whitespace) {
+            Some(index) => (&line[..index], line[index..].trim()),
+            None => (line, ""),
+        };
+
+        match command {
+            "" => {}
+            "quit" | "exit" => break,
+            "help" => println!("{}", HELP),
+            "boards" => print_boards(&record.game),
+            "moves" => print_moves(&record.game, argument),
+            "eval" => match search(&record.game, &options) {
+                Some(((moves, _boards, _info, _), score)) => {
+                    println!("{} ({})", write_moveset(&record.game, &moves), score);
+                }
+                None => print_end(&record.game),
+            },
+            "undo" => {
+                if undo(&mut record, user) {
+                    print_boards(&record.game);
+                } else {
+                    println!("Nothing to undo");
+                }
+            }
+            "save" => save(&record, argument),
+            "go" => {
+                if engine_move(&mut record, &options) {
+                    print_boards(&record.game);
+                }
+            }
+            _ => {
+                let moveset = match notation::parse_moveset(&record.game, line) {
+                    Ok(moveset) => moveset,
+                    Err(kind) => {
+                        println!("{}", kind);
+                        continue;
+                    }
+                };
+                if let Err(kind) = replay::play_moveset(&mut record.game, &moveset) {
+                    println!("Illegal moveset: {}", kind);
+                    continue;
+                }
+                user = Some(!record.game.info.active_player);
+                record.turns.push(moveset);
+                engine_move(&mut record, &options);
+                print_boards(&record.game);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Lets the engine play for the active player; returns false if no moveset could be played
+fn engine_move(record: &mut GameRecord, options: &SearchOptions) -> bool {
+    match search(&record.game, options) {
+        Some(((moves, _boards, _info, _), score)) => {
+            let moves = moves.into_iter().filter(|mv| !mv.noop).collect::<Vec<_>>();
+            let written = write_moveset(&record.game, &moves);
+            match replay::play_moveset(&mut record.game, &moves) {
+                Ok(()) => {
+                    println!("Engine plays {} ({})", written, score);
+                    record.turns.push(moves);
+                    true
+                }
+                Err(kind) => {
+                    println!("The engine found an illegal moveset ({}): {}", written, kind);
+                    false
+                }
+            }
+        }
+        None => {
+            print_end(&record.game);
+            false
+        }
+    }
+}
+
+/// Takes back the last moveset, then the ones before it until it is `user`'s turn again; returns false if there was nothing to undo
+fn undo(record: &mut GameRecord, user: Option<bool>) -> bool {
+    if !record.game.undo() {
+        return false;
+    }
+    record.turns.pop();
+    while user.map(|white| white != record.game.info.active_player).unwrap_or(false) && record.game.undo() {
+        record.turns.pop();
+    }
+    true
+}
+
+fn write_moveset(game: &Game, moves: &[Move]) -> String {
+    moves
+        .iter()
+        .filter(|mv| !mv.noop)
+        .map(|mv| notation::write_move(game, mv))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Displays the last board of every active timeline
+fn print_boards(game: &Game) {
+    // Boards past the present are still displayed
+    let probe = GameInfo {
+        present: std::isize::MAX,
+        ..game.info
+    };
+    let mut timelines = game.timelines.values().collect::<Vec<_>>();
+    timelines.sort_by_key(|tl| tl.index);
+    for tl in timelines {
+        if let Some(b) = tl.get_last_board() {
+            if b.is_active(&probe) {
+                println!("{}", b);
+                println!(
+                    "{}{}\n",
+                    notation::write_board(b, game.info.even_initial_timelines),
+                    if b.active_player() { "w" } else { "b" }
+                );
+            }
+        }
+    }
+    println!(
+        "Turn {}, {} to play",
+        notation::turn_number(game.info.present),
+        if game.info.active_player { "white" } else { "black" }
+    );
+}
+
+fn print_moves(game: &Game, argument: &str) {
+    let board = notation::parse_board(argument, game.info.active_player, game.info.even_initial_timelines)
+        .and_then(|(l, t)| game.get_board(l, t));
+    match board {
+        Some(board) => {
+            for mv in probable_moves(game, board, &vec![]) {
+                println!("{}", notation::write_move(game, &mv));
+            }
+        }
+        None => println!("No such board: {}", argument),
+    }
+}
+
+fn print_end(game: &Game) {
+    match game_status(game) {
+        Status::Checkmate(white) => println!("Checkmate! {} wins!", if white { "White" } else { "Black" }),
+        Status::Stalemate => println!("Draw!"),
+        Status::Ongoing => println!("The engine couldn't find a moveset within its search limits"),
+    }
+}
+
+fn save(record: &GameRecord, path: &str) {
+    if path.is_empty() {
+        println!("Usage: save <path>");
+        return;
+    }
+    match notation::write(record) {
+        Ok(contents) => match std::fs::write(path, contents) {
+            Ok(()) => println!("Saved to {}", path),
+            Err(err) => println!("Couldn't write {}: {}", path, err),
+        },
+        Err(err) => println!("Couldn't write the game: {}", err),
+    }
+}